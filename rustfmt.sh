@@ -1,3 +1,21 @@
 # This script runs rustfmt for the parts of the repo that uses it
 
 rustfmt ./src/syscalls.rs
+rustfmt ./src/call.rs
+rustfmt ./src/codec.rs
+rustfmt ./src/interface.rs
+rustfmt ./src/proxy.rs
+rustfmt ./src/context.rs
+rustfmt ./src/gas.rs
+rustfmt ./src/coins.rs
+rustfmt ./src/tx.rs
+rustfmt ./src/events.rs
+rustfmt ./src/element.rs
+rustfmt ./src/hashing.rs
+rustfmt ./src/collections.rs
+rustfmt ./src/layout.rs
+rustfmt ./src/cache.rs
+rustfmt ./src/journal.rs
+rustfmt ./src/versioning.rs
+rustfmt ./src/packed.rs
+rustfmt ./src/mock.rs
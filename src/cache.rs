@@ -0,0 +1,376 @@
+//! Opt-in write-back cache for contract state
+//!
+//! Every storage access is a full hypervisor round trip. Once `enable` is called, values loaded or stored through `storage`
+//! are kept in a fixed size cache, so that repeated reads of a key are served from memory and repeated writes only reach the
+//! hypervisor once. Dirty entries are written back when the cache runs out of space, before calling another contract,
+//! and by `flush`, which the `neutron_interface!` dispatcher calls once a method returns.
+//! Contracts which don't use the dispatcher run their whole execution through `run`, which flushes once it succeeds,
+//! otherwise their writes are lost when the execution finishes.
+//!
+//! Keys longer than MAX_CACHED_KEY_SIZE and values longer than MAX_CACHED_VALUE_SIZE bypass the cache.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::codec::MAX_ENCODED_SIZE;
+use crate::collections::MAX_DERIVED_KEY_SIZE;
+use crate::storage::*;
+use crate::syscalls::*;
+
+pub const CACHE_CAPACITY: usize = 8;
+pub const MAX_CACHED_KEY_SIZE: usize = MAX_DERIVED_KEY_SIZE;
+pub const MAX_CACHED_VALUE_SIZE: usize = MAX_ENCODED_SIZE;
+
+#[derive(Clone, Copy, PartialEq)]
+enum EntryState {
+    Empty,
+    /// Same value as stored by the hypervisor
+    Clean,
+    /// Known to not exist in the hypervisor
+    Missing,
+    /// Written but not yet stored by the hypervisor
+    Dirty,
+    /// Deleted but not yet deleted by the hypervisor
+    Deleted,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    state: EntryState,
+    key: [u8; MAX_CACHED_KEY_SIZE],
+    key_size: usize,
+    value: [u8; MAX_CACHED_VALUE_SIZE],
+    value_size: usize,
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry {
+        state: EntryState::Empty,
+        key: [0; MAX_CACHED_KEY_SIZE],
+        key_size: 0,
+        value: [0; MAX_CACHED_VALUE_SIZE],
+        value_size: 0,
+    };
+
+    fn key(&self) -> &[u8] {
+        &self.key[..self.key_size]
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.value[..self.value_size]
+    }
+
+    fn set_key(&mut self, key: &[u8]) {
+        self.key[..key.len()].copy_from_slice(key);
+        self.key_size = key.len();
+    }
+
+    fn set_value(&mut self, value: &[u8]) {
+        self.value[..value.len()].copy_from_slice(value);
+        self.value_size = value.len();
+    }
+
+    fn write_back(&mut self) -> Result<(), SystemError> {
+        match self.state {
+            EntryState::Dirty => {
+                store_state_uncached(self.key(), self.value())?;
+                self.state = EntryState::Clean;
+            }
+            EntryState::Deleted => {
+                delete_state_uncached(self.key())?;
+                self.state = EntryState::Missing;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+struct Cache {
+    entries: [Entry; CACHE_CAPACITY],
+}
+
+impl Cache {
+    fn find(&self, key: &[u8]) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.state != EntryState::Empty && entry.key() == key)
+    }
+
+    /// Find a slot for a new entry, writing back a dirty entry if every slot is used
+    fn free_slot(&mut self) -> Result<usize, SystemError> {
+        let unused = self.entries.iter().position(|entry| entry.state == EntryState::Empty);
+        let clean = || {
+            self.entries
+                .iter()
+                .position(|entry| entry.state == EntryState::Clean || entry.state == EntryState::Missing)
+        };
+        let slot = unused.or_else(clean).unwrap_or(0);
+        self.entries[slot].write_back()?;
+        self.entries[slot].state = EntryState::Empty;
+        Ok(slot)
+    }
+}
+
+struct CacheCell(UnsafeCell<Cache>);
+
+// Contracts are single threaded, and the cache is only borrowed within a single call to `with_cache`
+unsafe impl Sync for CacheCell {}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CACHE: CacheCell = CacheCell(UnsafeCell::new(Cache {
+    entries: [Entry::EMPTY; CACHE_CAPACITY],
+}));
+
+fn with_cache<R>(f: impl FnOnce(&mut Cache) -> R) -> R {
+    unsafe { f(&mut *CACHE.0.get()) }
+}
+
+/// Start caching state for the rest of the execution
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Write back all dirty entries and stop caching state
+pub fn disable() -> Result<(), SystemError> {
+    flush()?;
+    invalidate();
+    ENABLED.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Run the whole execution of a contract with the cache enabled, writing back all dirty entries once `body` succeeds
+///
+/// This is the exit path for contracts which don't dispatch through `neutron_interface!`. If `body` fails,
+/// dirty entries are not written back, as the contract is expected to revert.
+pub fn run<T, E: From<SystemError>>(body: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    enable();
+    let result = body()?;
+    flush()?;
+    Ok(result)
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Write back all dirty entries to the hypervisor. The entries are kept cached.
+pub fn flush() -> Result<(), SystemError> {
+    if !is_enabled() {
+        return Ok(());
+    }
+    with_cache(|cache| {
+        for entry in cache.entries.iter_mut() {
+            entry.write_back()?;
+        }
+        Ok(())
+    })
+}
+
+/// Forget all entries which are not dirty, so that they are loaded again from the hypervisor
+///
+/// Used after calling another contract, which may have modified the state of this contract through a reentrant call.
+pub fn invalidate() {
+    if !is_enabled() {
+        return;
+    }
+    with_cache(|cache| {
+        for entry in cache.entries.iter_mut() {
+            if entry.state == EntryState::Clean || entry.state == EntryState::Missing {
+                entry.state = EntryState::Empty;
+            }
+        }
+    })
+}
+
+/// Forget every entry, including dirty ones, and stop caching state
+#[cfg(test)]
+pub(crate) fn reset() {
+    with_cache(|cache| cache.entries = [Entry::EMPTY; CACHE_CAPACITY]);
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// Load through the cache, or None if the key bypasses the cache
+pub(crate) fn load(key: &[u8], buffer: &mut [u8]) -> Result<Option<LoadOutcome>, SystemError> {
+    if !is_enabled() || key.len() > MAX_CACHED_KEY_SIZE {
+        return Ok(None);
+    }
+    with_cache(|cache| {
+        let slot = match cache.find(key) {
+            Some(slot) => slot,
+            None => {
+                let slot = cache.free_slot()?;
+                let entry = &mut cache.entries[slot];
+                entry.set_key(key);
+                match load_state_uncached(key, &mut entry.value)? {
+                    LoadOutcome::Missing => entry.state = EntryState::Missing,
+                    LoadOutcome::Complete { len } => {
+                        entry.value_size = len;
+                        entry.state = EntryState::Clean;
+                    }
+                    // Too large to cache, so the caller loads it again directly
+                    LoadOutcome::Truncated { .. } => return Ok(None),
+                }
+                slot
+            }
+        };
+        let entry = &cache.entries[slot];
+        match entry.state {
+            EntryState::Clean | EntryState::Dirty => Ok(Some(copy_loaded(entry.value(), buffer))),
+            _ => Ok(Some(LoadOutcome::Missing)),
+        }
+    })
+}
+
+/// Store or, if `value` is None, delete through the cache. Returns false if the write bypasses the cache.
+pub(crate) fn store(key: &[u8], value: Option<&[u8]>) -> Result<bool, SystemError> {
+    if !is_enabled() {
+        return Ok(false);
+    }
+    with_cache(|cache| {
+        let existing = cache.find(key);
+        if key.len() > MAX_CACHED_KEY_SIZE || value.is_some_and(|value| value.len() > MAX_CACHED_VALUE_SIZE) {
+            // The write goes directly to the hypervisor and replaces any cached value
+            if let Some(slot) = existing {
+                cache.entries[slot].state = EntryState::Empty;
+            }
+            return Ok(false);
+        }
+        let slot = match existing {
+            Some(slot) => slot,
+            None => cache.free_slot()?,
+        };
+        let entry = &mut cache.entries[slot];
+        entry.set_key(key);
+        match value {
+            Some(value) => {
+                entry.set_value(value);
+                entry.state = EntryState::Dirty;
+            }
+            None => entry.state = EntryState::Deleted,
+        }
+        Ok(true)
+    })
+}
+
+/// Whether a key exists according to the cache, or None if it isn't cached
+pub(crate) fn exists(key: &[u8]) -> Option<bool> {
+    if !is_enabled() {
+        return None;
+    }
+    with_cache(|cache| {
+        cache
+            .find(key)
+            .map(|slot| matches!(cache.entries[slot].state, EntryState::Clean | EntryState::Dirty))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::call::*;
+    use crate::codec::*;
+    use crate::mock;
+    use neutron_common::NeutronAddress;
+    use std::format;
+    use std::vec::Vec;
+
+    fn load(key: &[u8]) -> Option<Vec<u8>> {
+        let mut buffer = [0; 16];
+        match load_state_fixed(key, &mut buffer).unwrap() {
+            LoadOutcome::Missing => None,
+            LoadOutcome::Complete { len } => Some(buffer[..len].to_vec()),
+            LoadOutcome::Truncated { .. } => panic!("value is larger than the test buffer"),
+        }
+    }
+
+    #[test]
+    fn writes_are_coalesced_until_flush() {
+        let _serial = mock::serial();
+        enable();
+        store_state(b"key", b"1").unwrap();
+        store_state(b"key", b"2").unwrap();
+        assert_eq!(load(b"key"), Some(b"2".to_vec()));
+        assert!(mock::calls().is_empty());
+        flush().unwrap();
+        assert_eq!(mock::stored(b"key"), Some(b"2".to_vec()));
+        assert_eq!(mock::calls().len(), 1);
+    }
+
+    #[test]
+    fn eviction_writes_back_when_every_entry_is_dirty() {
+        let _serial = mock::serial();
+        enable();
+        for i in 0..CACHE_CAPACITY {
+            store_state(format!("key{}", i).as_bytes(), b"value").unwrap();
+        }
+        assert!(mock::calls().is_empty());
+        store_state(b"other", b"value").unwrap();
+        // The first slot is written back to make room
+        assert_eq!(mock::stored(b"key0"), Some(b"value".to_vec()));
+        assert_eq!(mock::stored(b"key1"), None);
+        assert_eq!(mock::stored(b"other"), None);
+        flush().unwrap();
+        for i in 0..CACHE_CAPACITY {
+            assert_eq!(mock::stored(format!("key{}", i).as_bytes()), Some(b"value".to_vec()));
+        }
+        assert_eq!(mock::stored(b"other"), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn deleted_entry_becomes_missing() {
+        let _serial = mock::serial();
+        mock::set_stored(b"key", b"value");
+        enable();
+        delete_state(b"key").unwrap();
+        assert!(!key_exists(b"key").unwrap());
+        assert_eq!(mock::stored(b"key"), Some(b"value".to_vec()));
+        flush().unwrap();
+        assert_eq!(mock::stored(b"key"), None);
+        // The key stays cached as missing, so checking it again doesn't reach the hypervisor
+        mock::clear_calls();
+        assert!(!key_exists(b"key").unwrap());
+        assert_eq!(load(b"key"), None);
+        assert!(mock::calls().is_empty());
+    }
+
+    #[test]
+    fn calls_write_back_and_invalidate() {
+        let _serial = mock::serial();
+        mock::set_stored(b"clean", b"old");
+        enable();
+        assert_eq!(load(b"clean"), Some(b"old".to_vec()));
+        store_state(b"dirty", b"value").unwrap();
+        // The callee modifies the state of this contract, the contract call element itself is unavailable in the mock
+        mock::set_stored(b"clean", b"new");
+        let address = NeutronAddress::decode(&[0; core::mem::size_of::<NeutronAddress>()]).unwrap();
+        assert!(call_contract(&address, 1000, 0).is_err());
+        assert_eq!(mock::stored(b"dirty"), Some(b"value".to_vec()));
+        assert_eq!(load(b"clean"), Some(b"new".to_vec()));
+    }
+
+    #[test]
+    fn disable_flushes() {
+        let _serial = mock::serial();
+        enable();
+        store_state(b"key", b"value").unwrap();
+        assert_eq!(mock::stored(b"key"), None);
+        disable().unwrap();
+        assert!(!is_enabled());
+        assert_eq!(mock::stored(b"key"), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn run_flushes_on_success() {
+        let _serial = mock::serial();
+        run(|| store_state(b"key", b"value")).unwrap();
+        assert_eq!(mock::stored(b"key"), Some(b"value".to_vec()));
+
+        let result: Result<(), SystemError> = run(|| {
+            store_state(b"other", b"value")?;
+            Err(SystemError::UnexpectedSize)
+        });
+        assert!(result.is_err());
+        assert_eq!(mock::stored(b"other"), None);
+    }
+}
@@ -0,0 +1,364 @@
+//! Calling into other contracts
+//!
+//! Arguments for a call are written into the outgoing comap (usually through a `ComapBuilder`),
+//! the call is performed with `call_contract`, and the callee's results are read back
+//! from the result comap through the returned `CallResult`.
+
+/*
+## Contract Calls
+
+ID: 3
+
+Functions:
+
+* call_contract(address, gas_limit, value) -> (exit_code)
+* deploy_contract(code) -> (exit_code, address)
+* deploy_contract_salted(code, salt) -> (exit_code, address)
+* delegate_call(address, gas_limit) -> (exit_code)
+
+The outgoing comap at the time of the call is used as the input comap of the callee.
+After the call returns, the callee's outgoing comap is available as the result comap of the caller.
+The exit code of the callee is returned as the system call result, where 0 indicates success.
+
+Deployment works the same way, with the outgoing comap used as the input of the new contract's constructor.
+The address of the new contract is only pushed to the costack when the constructor succeeds.
+The salted variant derives the address from the deploying contract, the code and the salt,
+so that the address is known before deployment. Deploying to an address which is already in use fails.
+
+A delegate call executes the code of another contract as if it were the current contract.
+It uses the current input comap rather than the outgoing comap, and operates on the storage of the current contract.
+The outgoing comap of the delegate is kept as the outgoing comap of the current contract.
+
+In view mode, deploying and calls sending a value fail with SystemError::WriteInView, as they change state.
+Other calls and delegate calls are only allowed in view mode while the whole execution is static,
+in which case the hypervisor runs the callee as a static call as well. From a #[view] method of a regular call
+the callee could modify state, so these calls fail with SystemError::WriteInView too.
+*/
+
+extern crate neutron_common;
+
+use neutron_common::*;
+
+use crate::cache;
+use crate::codec::*;
+use crate::context::*;
+use crate::storage::*;
+use crate::syscalls::*;
+
+enum ContractCallFunctions {
+    CallContract = 1,
+    DeployContract,
+    DeployContractSalted,
+    DelegateCall,
+}
+
+system_element! {
+    const CONTRACT_CALL_ELEMENT = 3;
+
+    fn element_call_contract(address: &NeutronAddress, gas_limit: u64, value: u64) = ContractCallFunctions::CallContract as u32;
+    fn element_deploy_contract(code: &[u8]) = ContractCallFunctions::DeployContract as u32;
+    fn element_deploy_contract_salted(code: &[u8], salt: &[u8]) = ContractCallFunctions::DeployContractSalted as u32;
+    fn element_delegate_call(address: &NeutronAddress, gas_limit: u64) = ContractCallFunctions::DelegateCall as u32;
+}
+
+#[derive(core::fmt::Debug)]
+pub enum CallError {
+    /// The callee finished with a non-zero exit code
+    Reverted(u32),
+    /// The callee succeeded, but its results could not be decoded
+    InvalidResult(RecoverableError),
+    /// The hypervisor refused or failed to perform the call
+    System(SystemError),
+}
+
+impl From<SystemError> for CallError {
+    fn from(error: SystemError) -> CallError {
+        CallError::System(error)
+    }
+}
+
+// Other contracts read state from the hypervisor and may modify the state of this contract by calling back into it,
+// so the storage cache is written back before the call and reloaded after it
+fn around_call(call: impl FnOnce() -> Result<u32, SystemError>) -> Result<u32, SystemError> {
+    cache::flush()?;
+    let result = call();
+    cache::invalidate();
+    result
+}
+
+fn check_not_view() -> Result<(), SystemError> {
+    if is_view_mode() {
+        return Err(SystemError::WriteInView);
+    }
+    Ok(())
+}
+
+fn check_callee_static() -> Result<(), SystemError> {
+    if is_view_mode() && execution_mode()? != ExecutionMode::Static {
+        return Err(SystemError::WriteInView);
+    }
+    Ok(())
+}
+
+/// Call another contract using the current contents of the outgoing comap as its input.
+pub fn call_contract(address: &NeutronAddress, gas_limit: u64, value: u64) -> Result<CallResult, CallError> {
+    if value > 0 {
+        check_not_view()?;
+    }
+    check_callee_static()?;
+    match around_call(|| element_call_contract(address, gas_limit, value))? {
+        0 => Ok(CallResult { _private: () }),
+        code => Err(CallError::Reverted(code)),
+    }
+}
+
+/// Execute the code of another contract against the storage of this contract, forwarding the whole input comap.
+pub fn delegate_call(address: &NeutronAddress, gas_limit: u64) -> Result<(), CallError> {
+    check_callee_static()?;
+    match around_call(|| element_delegate_call(address, gas_limit))? {
+        0 => Ok(()),
+        code => Err(CallError::Reverted(code)),
+    }
+}
+
+/// Deploy a new contract, calling its constructor with the arguments written to `init_args`.
+pub fn deploy_contract(code: &[u8], init_args: ComapBuilder) -> Result<NeutronAddress, CallError> {
+    // The arguments are already in the outgoing comap, the builder is only taken so it can't be reused afterwards
+    let _ = init_args;
+    check_not_view()?;
+    match around_call(|| element_deploy_contract(code))? {
+        0 => pop_costack_address().map_err(CallError::InvalidResult),
+        code => Err(CallError::Reverted(code)),
+    }
+}
+
+/// Deploy a new contract to an address determined by this contract, the code, and `salt`.
+pub fn deploy_contract_salted(code: &[u8], init_args: ComapBuilder, salt: &[u8]) -> Result<NeutronAddress, CallError> {
+    let _ = init_args;
+    check_not_view()?;
+    match around_call(|| element_deploy_contract_salted(code, salt))? {
+        0 => pop_costack_address().map_err(CallError::InvalidResult),
+        code => Err(CallError::Reverted(code)),
+    }
+}
+
+/// Builder for the outgoing comap used as the input of a contract call.
+/// Every method writes directly into the outgoing comap, so values are visible to the callee
+/// as soon as they are written.
+#[derive(Default)]
+pub struct ComapBuilder {
+    _private: (),
+}
+
+impl ComapBuilder {
+    pub fn new() -> ComapBuilder {
+        ComapBuilder::default()
+    }
+
+    /// Write any encodable value, using the ABI value header of its type
+    pub fn value<T: CostackEncode + ?Sized>(self, key: &str, value: &T) -> Self {
+        write_comap(key, value);
+        self
+    }
+
+    pub fn u8(self, key: &str, value: u8) -> Self {
+        write_comap_u8(key, value);
+        self
+    }
+
+    pub fn u16(self, key: &str, value: u16) -> Self {
+        write_comap_u16(key, value);
+        self
+    }
+
+    pub fn u32(self, key: &str, value: u32) -> Self {
+        write_comap_u32(key, value);
+        self
+    }
+
+    pub fn u64(self, key: &str, value: u64) -> Self {
+        write_comap_u64(key, value);
+        self
+    }
+
+    pub fn i8(self, key: &str, value: i8) -> Self {
+        write_comap_i8(key, value);
+        self
+    }
+
+    pub fn i16(self, key: &str, value: i16) -> Self {
+        write_comap_i16(key, value);
+        self
+    }
+
+    pub fn i32(self, key: &str, value: i32) -> Self {
+        write_comap_i32(key, value);
+        self
+    }
+
+    pub fn i64(self, key: &str, value: i64) -> Self {
+        write_comap_i64(key, value);
+        self
+    }
+
+    pub fn address(self, key: &str, value: NeutronAddress) -> Self {
+        write_comap_address(key, value);
+        self
+    }
+
+    pub fn array_u8(self, key: &str, value: &[u8]) -> Self {
+        write_comap_array_u8(key, value);
+        self
+    }
+
+    pub fn array_u16(self, key: &str, value: &[u16]) -> Self {
+        write_comap_array_u16(key, value);
+        self
+    }
+
+    pub fn array_u32(self, key: &str, value: &[u32]) -> Self {
+        write_comap_array_u32(key, value);
+        self
+    }
+
+    pub fn array_u64(self, key: &str, value: &[u64]) -> Self {
+        write_comap_array_u64(key, value);
+        self
+    }
+
+    pub fn array_i8(self, key: &str, value: &[i8]) -> Self {
+        write_comap_array_i8(key, value);
+        self
+    }
+
+    pub fn array_i16(self, key: &str, value: &[i16]) -> Self {
+        write_comap_array_i16(key, value);
+        self
+    }
+
+    pub fn array_i32(self, key: &str, value: &[i32]) -> Self {
+        write_comap_array_i32(key, value);
+        self
+    }
+
+    pub fn array_i64(self, key: &str, value: &[i64]) -> Self {
+        write_comap_array_i64(key, value);
+        self
+    }
+
+    /// Perform the call with the arguments written so far. Equivalent to `call_contract`.
+    pub fn call(self, address: &NeutronAddress, gas_limit: u64, value: u64) -> Result<CallResult, CallError> {
+        call_contract(address, gas_limit, value)
+    }
+}
+
+/// Typed access to the result comap of a successful contract call.
+///
+/// The hypervisor keeps a single result comap, which is replaced by every later call.
+/// Reading through a `CallResult` after another call therefore reads the results of that later call,
+/// so results should be read before making the next call.
+pub struct CallResult {
+    _private: (),
+}
+
+impl CallResult {
+    /// Read any decodable value, checking the ABI value header of its type
+    pub fn read<T: CostackDecode>(&self, key: &str) -> Result<T, RecoverableError> {
+        read_result_comap(key)
+    }
+
+    pub fn u8(&self, key: &str) -> Result<u8, RecoverableError> {
+        read_result_comap_u8(key)
+    }
+
+    pub fn u16(&self, key: &str) -> Result<u16, RecoverableError> {
+        read_result_comap_u16(key)
+    }
+
+    pub fn u32(&self, key: &str) -> Result<u32, RecoverableError> {
+        read_result_comap_u32(key)
+    }
+
+    pub fn u64(&self, key: &str) -> Result<u64, RecoverableError> {
+        read_result_comap_u64(key)
+    }
+
+    pub fn i8(&self, key: &str) -> Result<i8, RecoverableError> {
+        read_result_comap_i8(key)
+    }
+
+    pub fn i16(&self, key: &str) -> Result<i16, RecoverableError> {
+        read_result_comap_i16(key)
+    }
+
+    pub fn i32(&self, key: &str) -> Result<i32, RecoverableError> {
+        read_result_comap_i32(key)
+    }
+
+    pub fn i64(&self, key: &str) -> Result<i64, RecoverableError> {
+        read_result_comap_i64(key)
+    }
+
+    pub fn fixed_array_u8(&self, key: &str, return_slice: &mut [u8]) -> Result<u32, RecoverableError> {
+        read_result_comap_fixed_array_u8(key, return_slice)
+    }
+
+    pub fn fixed_array_u16(&self, key: &str, return_slice: &mut [u16]) -> Result<u32, RecoverableError> {
+        read_result_comap_fixed_array_u16(key, return_slice)
+    }
+
+    pub fn fixed_array_u32(&self, key: &str, return_slice: &mut [u32]) -> Result<u32, RecoverableError> {
+        read_result_comap_fixed_array_u32(key, return_slice)
+    }
+
+    pub fn fixed_array_u64(&self, key: &str, return_slice: &mut [u64]) -> Result<u32, RecoverableError> {
+        read_result_comap_fixed_array_u64(key, return_slice)
+    }
+
+    pub fn fixed_array_i8(&self, key: &str, return_slice: &mut [i8]) -> Result<u32, RecoverableError> {
+        read_result_comap_fixed_array_i8(key, return_slice)
+    }
+
+    pub fn fixed_array_i16(&self, key: &str, return_slice: &mut [i16]) -> Result<u32, RecoverableError> {
+        read_result_comap_fixed_array_i16(key, return_slice)
+    }
+
+    pub fn fixed_array_i32(&self, key: &str, return_slice: &mut [i32]) -> Result<u32, RecoverableError> {
+        read_result_comap_fixed_array_i32(key, return_slice)
+    }
+
+    pub fn fixed_array_i64(&self, key: &str, return_slice: &mut [i64]) -> Result<u32, RecoverableError> {
+        read_result_comap_fixed_array_i64(key, return_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    fn address() -> NeutronAddress {
+        NeutronAddress::decode(&[7; core::mem::size_of::<NeutronAddress>()]).unwrap()
+    }
+
+    #[test]
+    fn typed_address_is_read_generically() {
+        let _serial = mock::serial();
+        ComapBuilder::new().address("to", address());
+        assert!(read_comap::<NeutronAddress>("to").unwrap() == address());
+        let result = CallResult { _private: () };
+        assert!(result.read::<NeutronAddress>("to").unwrap() == address());
+    }
+
+    #[test]
+    fn generic_address_matches_typed_address() {
+        let _serial = mock::serial();
+        ComapBuilder::new().value("generic", &address()).address("typed", address());
+        let result = CallResult { _private: () };
+        let mut generic = [0; core::mem::size_of::<NeutronAddress>()];
+        let mut typed = [0; core::mem::size_of::<NeutronAddress>()];
+        assert_eq!(result.fixed_array_u8("generic", &mut generic).unwrap() as usize, generic.len());
+        assert_eq!(result.fixed_array_u8("typed", &mut typed).unwrap() as usize, typed.len());
+        assert_eq!(generic, typed);
+    }
+}
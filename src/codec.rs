@@ -0,0 +1,481 @@
+//! Encoding of typed values to and from the costack
+//!
+//! `CostackEncode` and `CostackDecode` describe how a value is laid out as a single costack item,
+//! and which ABI value header it uses when it is stored in a comap.
+//! The generic comap functions in this module are used by code which only knows the type of a value,
+//! such as the interface macros. The typed `write_comap_XXX`/`read_comap_XXX` functions in `syscalls` remain available.
+//!
+//! Structs declared with `neutron_struct!` are encoded as a byte array holding each field in order,
+//! with every field prefixed by a single byte giving its encoded size. When stored, they are also prefixed by their
+//! storage version, see the `versioning` module.
+
+extern crate neutron_common;
+extern crate neutron_star_rt;
+
+use core::mem::transmute;
+
+use neutron_common::*;
+use neutron_star_rt::*;
+
+use crate::syscalls::*;
+
+pub use neutron_common::RecoverableError;
+
+/// The largest encoded size supported by the default `push_costack` and `pop_costack` implementations
+pub const MAX_ENCODED_SIZE: usize = 256;
+
+pub trait CostackEncode {
+    /// The ABI value header used when this type is written to a comap
+    const ABI_VALUE: u32;
+    /// Canonical name of the type, used in method selectors and event signatures so that they don't depend
+    /// on how the type is written in the source. See `interface::method_selector`.
+    const ABI_NAME: &'static str;
+    /// The version written before the encoding when the value is stored, or None to store only the encoding.
+    /// See the `versioning` module.
+    const STORAGE_VERSION: Option<u8> = None;
+
+    /// Encode the value into `buffer` and return the number of bytes used
+    fn encode(&self, buffer: &mut [u8]) -> usize;
+
+    /// The encoding of the value if it is just its own bytes, as for byte slices and strings,
+    /// which can then be used without encoding them into a buffer of MAX_ENCODED_SIZE
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Push the encoded value to the costack as a single item
+    fn push_costack(&self) {
+        let mut buffer = [0; MAX_ENCODED_SIZE];
+        let size = self.encode(&mut buffer);
+        push_costack(&buffer[..size]);
+    }
+}
+
+pub trait CostackDecode: Sized {
+    /// The ABI value header expected when this type is read from a comap
+    const ABI_VALUE: u32;
+    /// The largest number of bytes a valid encoding of this type can use
+    const MAX_SIZE: usize;
+    /// The current version of the type when it is stored, the same as `CostackEncode::STORAGE_VERSION`
+    const STORAGE_VERSION: Option<u8> = None;
+
+    /// Decode a value from exactly the bytes of a single costack item
+    fn decode(bytes: &[u8]) -> Result<Self, RecoverableError>;
+
+    /// Decode a value stored with `version` of this type, migrating it to the current version if it is older
+    fn decode_version(version: u8, bytes: &[u8]) -> Result<Self, RecoverableError> {
+        if Self::STORAGE_VERSION == Some(version) {
+            Self::decode(bytes)
+        } else {
+            Err(RecoverableError::ItemDoesntExist) // TODO: Replace with neutron-star error
+        }
+    }
+
+    /// Pop a single item from the costack and decode it
+    fn pop_costack() -> Result<Self, RecoverableError> {
+        let mut buffer = [0; MAX_ENCODED_SIZE];
+        let actual_size = match pop_costack_fixed(&mut buffer[..Self::MAX_SIZE]) {
+            Ok(v) => v as usize,
+            Err(_e) => return Err(RecoverableError::ItemDoesntExist),
+        };
+        if actual_size > Self::MAX_SIZE {
+            return Err(RecoverableError::StackItemTooLarge);
+        }
+        Self::decode(&buffer[..actual_size])
+    }
+}
+
+macro_rules! impl_costack_codec_integer {
+    ($TYPE:tt, $ABI_VALUE:expr) => {
+        impl CostackEncode for $TYPE {
+            const ABI_VALUE: u32 = $ABI_VALUE;
+            const ABI_NAME: &'static str = stringify!($TYPE);
+
+            fn encode(&self, buffer: &mut [u8]) -> usize {
+                const TYPE_SIZE: usize = core::mem::size_of::<$TYPE>();
+                buffer[..TYPE_SIZE].copy_from_slice(&self.to_le_bytes());
+                TYPE_SIZE
+            }
+        }
+
+        impl CostackDecode for $TYPE {
+            const ABI_VALUE: u32 = $ABI_VALUE;
+            const MAX_SIZE: usize = core::mem::size_of::<$TYPE>();
+
+            fn decode(bytes: &[u8]) -> Result<Self, RecoverableError> {
+                const TYPE_SIZE: usize = core::mem::size_of::<$TYPE>();
+                // Only the exact expected byte count is allowed, same as pop_costack_XXX
+                if bytes.len() > TYPE_SIZE {
+                    return Err(RecoverableError::StackItemTooLarge);
+                } else if bytes.len() < TYPE_SIZE {
+                    return Err(RecoverableError::StackItemTooSmall);
+                }
+                let mut value = [0; TYPE_SIZE];
+                value.copy_from_slice(bytes);
+                Ok(<$TYPE>::from_le_bytes(value))
+            }
+        }
+    };
+}
+
+impl_costack_codec_integer!(u8, ABI_VALUE_U8);
+impl_costack_codec_integer!(u16, ABI_VALUE_U16);
+impl_costack_codec_integer!(u32, ABI_VALUE_U32);
+impl_costack_codec_integer!(u64, ABI_VALUE_U64);
+impl_costack_codec_integer!(i8, ABI_VALUE_I8);
+impl_costack_codec_integer!(i16, ABI_VALUE_I16);
+impl_costack_codec_integer!(i32, ABI_VALUE_I32);
+impl_costack_codec_integer!(i64, ABI_VALUE_I64);
+
+// bool is encoded as a u8 which must be either 0 or 1
+impl CostackEncode for bool {
+    const ABI_VALUE: u32 = ABI_VALUE_U8;
+    const ABI_NAME: &'static str = "bool";
+
+    fn encode(&self, buffer: &mut [u8]) -> usize {
+        buffer[0] = *self as u8;
+        1
+    }
+}
+
+impl CostackDecode for bool {
+    const ABI_VALUE: u32 = ABI_VALUE_U8;
+    const MAX_SIZE: usize = 1;
+
+    fn decode(bytes: &[u8]) -> Result<Self, RecoverableError> {
+        match bytes {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            [] => Err(RecoverableError::StackItemTooSmall),
+            _ => Err(RecoverableError::StackItemTooLarge), // TODO: Replace with neutron-star error
+        }
+    }
+}
+
+// NeutronAddress is encoded the same way as push_costack_address/pop_costack_address, as a byte array
+impl CostackEncode for NeutronAddress {
+    const ABI_VALUE: u32 = ABI_VALUE_ADDRESS;
+    const ABI_NAME: &'static str = "address";
+
+    fn encode(&self, buffer: &mut [u8]) -> usize {
+        const TYPE_SIZE: usize = core::mem::size_of::<NeutronAddress>();
+        let bytes = unsafe { transmute::<NeutronAddress, [u8; TYPE_SIZE]>(*self) };
+        buffer[..TYPE_SIZE].copy_from_slice(&bytes);
+        TYPE_SIZE
+    }
+}
+
+impl CostackDecode for NeutronAddress {
+    const ABI_VALUE: u32 = ABI_VALUE_ADDRESS;
+    const MAX_SIZE: usize = core::mem::size_of::<NeutronAddress>();
+
+    fn decode(bytes: &[u8]) -> Result<Self, RecoverableError> {
+        const TYPE_SIZE: usize = core::mem::size_of::<NeutronAddress>();
+        if bytes.len() > TYPE_SIZE {
+            return Err(RecoverableError::StackItemTooLarge);
+        } else if bytes.len() < TYPE_SIZE {
+            return Err(RecoverableError::StackItemTooSmall);
+        }
+        let mut value = [0; TYPE_SIZE];
+        value.copy_from_slice(bytes);
+        Ok(unsafe { transmute::<[u8; TYPE_SIZE], NeutronAddress>(value) })
+    }
+}
+
+// Unit is encoded as an empty byte array, so that methods without a return value still produce a result
+impl CostackEncode for () {
+    const ABI_VALUE: u32 = ABI_VALUE_U8 + ABI_ARRAY_BIT;
+    const ABI_NAME: &'static str = "unit";
+
+    fn encode(&self, _buffer: &mut [u8]) -> usize {
+        0
+    }
+}
+
+impl CostackDecode for () {
+    const ABI_VALUE: u32 = ABI_VALUE_U8 + ABI_ARRAY_BIT;
+    const MAX_SIZE: usize = 0;
+
+    fn decode(_bytes: &[u8]) -> Result<Self, RecoverableError> {
+        Ok(())
+    }
+}
+
+// Byte slices are pushed directly and so are not limited to MAX_ENCODED_SIZE
+impl CostackEncode for [u8] {
+    const ABI_VALUE: u32 = ABI_VALUE_U8 + ABI_ARRAY_BIT;
+    const ABI_NAME: &'static str = "bytes";
+
+    fn encode(&self, buffer: &mut [u8]) -> usize {
+        buffer[..self.len()].copy_from_slice(self);
+        self.len()
+    }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+
+    fn push_costack(&self) {
+        push_costack(self);
+    }
+}
+
+impl CostackEncode for str {
+    const ABI_VALUE: u32 = ABI_VALUE_U8 + ABI_ARRAY_BIT;
+    const ABI_NAME: &'static str = "string";
+
+    fn encode(&self, buffer: &mut [u8]) -> usize {
+        self.as_bytes().encode(buffer)
+    }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        Some(self.as_bytes())
+    }
+
+    fn push_costack(&self) {
+        push_costack(self.as_bytes());
+    }
+}
+
+// Long enough for "bytes" followed by any usize
+const FIXED_BYTES_NAME_SIZE: usize = 25;
+
+/// Write the ABI name of a fixed size byte array, `bytes` followed by its size, and return the length of the name
+const fn fixed_bytes_name(size: usize) -> ([u8; FIXED_BYTES_NAME_SIZE], usize) {
+    let mut name = [0; FIXED_BYTES_NAME_SIZE];
+    let prefix = b"bytes";
+    let mut i = 0;
+    while i < prefix.len() {
+        name[i] = prefix[i];
+        i += 1;
+    }
+    let mut digits = 1;
+    let mut rest = size;
+    while rest >= 10 {
+        rest /= 10;
+        digits += 1;
+    }
+    rest = size;
+    let mut digit = 0;
+    while digit < digits {
+        name[i + digits - 1 - digit] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+        digit += 1;
+    }
+    (name, i + digits)
+}
+
+struct FixedBytesName<const N: usize>;
+
+impl<const N: usize> FixedBytesName<N> {
+    const NAME: ([u8; FIXED_BYTES_NAME_SIZE], usize) = fixed_bytes_name(N);
+}
+
+// Fixed size byte arrays, such as hashes, must be exactly the expected size when decoded
+impl<const N: usize> CostackEncode for [u8; N] {
+    const ABI_VALUE: u32 = ABI_VALUE_U8 + ABI_ARRAY_BIT;
+    const ABI_NAME: &'static str = match core::str::from_utf8(FixedBytesName::<N>::NAME.0.split_at(FixedBytesName::<N>::NAME.1).0) {
+        Ok(name) => name,
+        Err(_) => "bytes",
+    };
+
+    fn encode(&self, buffer: &mut [u8]) -> usize {
+        self[..].encode(buffer)
+    }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+
+    fn push_costack(&self) {
+        push_costack(self);
+    }
+}
+
+impl<const N: usize> CostackDecode for [u8; N] {
+    const ABI_VALUE: u32 = ABI_VALUE_U8 + ABI_ARRAY_BIT;
+    const MAX_SIZE: usize = N;
+
+    fn decode(bytes: &[u8]) -> Result<Self, RecoverableError> {
+        if bytes.len() > N {
+            return Err(RecoverableError::StackItemTooLarge);
+        } else if bytes.len() < N {
+            return Err(RecoverableError::StackItemTooSmall);
+        }
+        let mut value = [0; N];
+        value.copy_from_slice(bytes);
+        Ok(value)
+    }
+
+    // Arrays may be larger than MAX_ENCODED_SIZE, so pop directly into the result
+    fn pop_costack() -> Result<Self, RecoverableError> {
+        let mut value = [0; N];
+        match pop_costack_fixed(&mut value) {
+            Ok(size) if size as usize == N => Ok(value),
+            Ok(size) if size as usize > N => Err(RecoverableError::StackItemTooLarge),
+            Ok(_) => Err(RecoverableError::StackItemTooSmall),
+            Err(_) => Err(RecoverableError::ItemDoesntExist),
+        }
+    }
+}
+
+// References encode the same as the value they point to
+impl<T: CostackEncode + ?Sized> CostackEncode for &T {
+    const ABI_VALUE: u32 = T::ABI_VALUE;
+    const ABI_NAME: &'static str = T::ABI_NAME;
+    const STORAGE_VERSION: Option<u8> = T::STORAGE_VERSION;
+
+    fn encode(&self, buffer: &mut [u8]) -> usize {
+        (**self).encode(buffer)
+    }
+
+    fn encoded_bytes(&self) -> Option<&[u8]> {
+        (**self).encoded_bytes()
+    }
+
+    fn push_costack(&self) {
+        (**self).push_costack()
+    }
+}
+
+/// Encode a field of a `neutron_struct!` at `offset` and return the offset after it
+#[doc(hidden)]
+pub fn encode_struct_field<T: CostackEncode + ?Sized>(value: &T, buffer: &mut [u8], offset: usize) -> usize {
+    let size = value.encode(&mut buffer[offset + 1..]);
+    buffer[offset] = size as u8;
+    offset + 1 + size
+}
+
+/// Decode a field of a `neutron_struct!` and advance `bytes` past it
+#[doc(hidden)]
+pub fn decode_struct_field<T: CostackDecode>(bytes: &mut &[u8]) -> Result<T, RecoverableError> {
+    let (size, rest) = match bytes.split_first() {
+        Some((size, rest)) => (*size as usize, rest),
+        None => return Err(RecoverableError::StackItemTooSmall),
+    };
+    if rest.len() < size {
+        return Err(RecoverableError::StackItemTooSmall);
+    }
+    let value = T::decode(&rest[..size])?;
+    *bytes = &rest[size..];
+    Ok(value)
+}
+
+/// Declare a struct and implement `CostackEncode` and `CostackDecode` for it
+///
+/// ```ignore
+/// neutron_struct! {
+///     #[storage_version(2, migrate_from_v1(OrderV1))]
+///     #[derive(Default)]
+///     pub struct Order {
+///         pub owner: NeutronAddress,
+///         pub amount: u64,
+///         pub filled: bool,
+///     }
+/// }
+/// ```
+///
+/// Field types must implement both traits, and the encoded struct together with its storage version must fit within
+/// MAX_ENCODED_SIZE. The optional `#[storage_version]` must be the first attribute, see the `versioning` module.
+#[macro_export]
+macro_rules! neutron_struct {
+    (
+        @struct [$VERSION:literal $(, $MIGRATE:ident($PREVIOUS:ty))?]
+        $(#[$META:meta])*
+        $VIS:vis struct $NAME:ident {
+            $(
+                $(#[$FIELD_META:meta])*
+                $FIELD_VIS:vis $FIELD:ident : $FIELD_TYPE:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$META])*
+        $VIS struct $NAME {
+            $($(#[$FIELD_META])* $FIELD_VIS $FIELD: $FIELD_TYPE,)*
+        }
+
+        impl $crate::codec::CostackEncode for $NAME {
+            const ABI_VALUE: u32 = $crate::syscalls::ABI_VALUE_U8 + $crate::syscalls::ABI_ARRAY_BIT;
+            const ABI_NAME: &'static str = stringify!($NAME);
+            const STORAGE_VERSION: Option<u8> = Some($VERSION);
+
+            fn encode(&self, buffer: &mut [u8]) -> usize {
+                let offset = 0;
+                $(let offset = $crate::codec::encode_struct_field(&self.$FIELD, buffer, offset);)*
+                offset
+            }
+        }
+
+        impl $crate::codec::CostackDecode for $NAME {
+            const ABI_VALUE: u32 = $crate::syscalls::ABI_VALUE_U8 + $crate::syscalls::ABI_ARRAY_BIT;
+            const MAX_SIZE: usize = 0 $(+ 1 + <$FIELD_TYPE as $crate::codec::CostackDecode>::MAX_SIZE)*;
+            const STORAGE_VERSION: Option<u8> = Some($VERSION);
+
+            fn decode(bytes: &[u8]) -> Result<Self, $crate::codec::RecoverableError> {
+                let mut rest = bytes;
+                $(let $FIELD = $crate::codec::decode_struct_field::<$FIELD_TYPE>(&mut rest)?;)*
+                if !rest.is_empty() {
+                    return Err($crate::codec::RecoverableError::StackItemTooLarge);
+                }
+                Ok($NAME { $($FIELD),* })
+            }
+
+            fn decode_version(version: u8, bytes: &[u8]) -> Result<Self, $crate::codec::RecoverableError> {
+                if version == $VERSION {
+                    return <Self as $crate::codec::CostackDecode>::decode(bytes);
+                }
+                $crate::__neutron_decode_previous_version!(version, bytes $(, $MIGRATE($PREVIOUS))?)
+            }
+        }
+
+        const _: () = assert!(
+            <$NAME as $crate::codec::CostackDecode>::MAX_SIZE < $crate::codec::MAX_ENCODED_SIZE,
+            "encoded struct and its storage version are larger than MAX_ENCODED_SIZE"
+        );
+        $crate::__neutron_check_previous_version!($VERSION $(, $MIGRATE($PREVIOUS))?);
+    };
+    (
+        #[storage_version($VERSION:literal $(, $MIGRATE:ident($PREVIOUS:ty))? $(,)?)]
+        $($STRUCT:tt)*
+    ) => {
+        $crate::neutron_struct!(@struct [$VERSION $(, $MIGRATE($PREVIOUS))?] $($STRUCT)*);
+    };
+    ($($STRUCT:tt)*) => {
+        $crate::neutron_struct!(@struct [1] $($STRUCT)*);
+    };
+}
+
+/// Write any encodable value to the outgoing comap
+pub fn write_comap<T: CostackEncode + ?Sized>(key: &str, value: &T) {
+    unsafe {
+        push_costack(key.as_bytes());
+        value.push_costack();
+        __push_comap(T::ABI_VALUE);
+    }
+}
+
+/// Read any decodable value from the input comap
+pub fn read_comap<T: CostackDecode>(key: &str) -> Result<T, RecoverableError> {
+    let abi_value = unsafe {
+        push_costack(key.as_bytes());
+        __peek_comap(0, T::MAX_SIZE)
+    };
+    if abi_value == T::ABI_VALUE {
+        T::pop_costack()
+    } else {
+        Err(RecoverableError::ItemDoesntExist) // TODO: Custom neutron-star error
+    }
+}
+
+/// Read any decodable value from the result comap
+pub fn read_result_comap<T: CostackDecode>(key: &str) -> Result<T, RecoverableError> {
+    let abi_value = unsafe {
+        push_costack(key.as_bytes());
+        __peek_result_comap(0, T::MAX_SIZE)
+    };
+    if abi_value == T::ABI_VALUE {
+        T::pop_costack()
+    } else {
+        Err(RecoverableError::ItemDoesntExist) // TODO: Custom neutron-star error
+    }
+}
@@ -0,0 +1,75 @@
+//! Native coins sent with calls and held by contracts
+
+/*
+## Value Transfer
+
+ID: 7
+
+Functions:
+
+* value_received() -> (u64)
+* balance_of(address) -> (u64)
+* transfer(address, amount) -> ()
+
+value_received is the amount of coins sent along with the current call, which is already included in the balance of this contract.
+transfer returns 0 as the system call result on success, TRANSFER_INSUFFICIENT_BALANCE if the contract can't cover the amount,
+and any other value if the recipient refused the transfer.
+*/
+
+extern crate neutron_common;
+
+use neutron_common::*;
+
+use crate::context::*;
+use crate::storage::*;
+use crate::syscalls::*;
+
+enum ValueTransferFunctions {
+    ValueReceived = 1,
+    BalanceOf,
+    Transfer,
+}
+
+system_element! {
+    const VALUE_TRANSFER_ELEMENT = 7;
+
+    /// The amount of coins sent along with the current call
+    pub fn value_received() -> u64 = ValueTransferFunctions::ValueReceived as u32;
+    /// The amount of coins held by an address
+    pub fn balance_of(address: &NeutronAddress) -> u64 = ValueTransferFunctions::BalanceOf as u32;
+    fn element_transfer(to: &NeutronAddress, amount: u64) = ValueTransferFunctions::Transfer as u32;
+}
+
+const TRANSFER_INSUFFICIENT_BALANCE: u32 = 1;
+
+#[derive(core::fmt::Debug)]
+pub enum TransferError {
+    /// The balance of this contract is lower than the amount to transfer
+    InsufficientBalance,
+    /// The recipient refused the transfer, with the given result code
+    Rejected(u32),
+    System(SystemError),
+}
+
+impl From<SystemError> for TransferError {
+    fn from(error: SystemError) -> TransferError {
+        TransferError::System(error)
+    }
+}
+
+/// The amount of coins held by this contract, including the value received with the current call
+pub fn self_balance() -> Result<u64, SystemError> {
+    balance_of(&self_address()?)
+}
+
+/// Send coins from this contract to another address. Not allowed in view mode.
+pub fn transfer(to: &NeutronAddress, amount: u64) -> Result<(), TransferError> {
+    if is_view_mode() {
+        return Err(SystemError::WriteInView.into());
+    }
+    match element_transfer(to, amount)? {
+        0 => Ok(()),
+        TRANSFER_INSUFFICIENT_BALANCE => Err(TransferError::InsufficientBalance),
+        code => Err(TransferError::Rejected(code)),
+    }
+}
@@ -0,0 +1,739 @@
+//! Persistent collections built on the global storage element
+//!
+//! Every collection is given a key prefix, and stores each of its entries under that prefix followed by the
+//! encoded key of the entry. Encoded keys of SHA256_SIZE bytes or more are replaced by their sha256 hash,
+//! so that storage keys stay short. Directly used keys are always shorter than a hash, and so can't collide with hashed ones.
+//!
+//! Prefixes must be unique within a contract, and no prefix may be the start of another,
+//! otherwise entries of different collections could share a storage key. Prefixes can't be empty or overlap with
+//! RESERVED_KEY_PREFIX, which is checked when constructing a collection.
+
+use core::marker::PhantomData;
+
+use crate::codec::*;
+use crate::gas::*;
+use crate::hashing::*;
+use crate::storage::*;
+use crate::syscalls::*;
+
+/// The longest key prefix a collection can use
+pub const MAX_PREFIX_SIZE: usize = 32;
+/// The longest storage key derived for a collection entry
+pub const MAX_DERIVED_KEY_SIZE: usize = MAX_PREFIX_SIZE + SHA256_SIZE;
+
+/// Storage key of a collection entry
+pub struct DerivedKey {
+    buffer: [u8; MAX_DERIVED_KEY_SIZE],
+    size: usize,
+}
+
+impl DerivedKey {
+    /// Derive the storage key of `key` within the collection using `prefix`
+    pub fn new<K: CostackEncode + ?Sized>(prefix: &[u8], key: &K) -> Result<DerivedKey, SystemError> {
+        if prefix.len() > MAX_PREFIX_SIZE {
+            return Err(SystemError::UnexpectedSize);
+        }
+        // Byte keys may be longer than MAX_ENCODED_SIZE, so they are used without encoding them into a buffer
+        let mut encode_buffer = [0; MAX_ENCODED_SIZE];
+        let encoded = match key.encoded_bytes() {
+            Some(bytes) => bytes,
+            None => {
+                let size = key.encode(&mut encode_buffer);
+                &encode_buffer[..size]
+            }
+        };
+        let mut buffer = [0; MAX_DERIVED_KEY_SIZE];
+        buffer[..prefix.len()].copy_from_slice(prefix);
+        let size = if encoded.len() < SHA256_SIZE {
+            buffer[prefix.len()..prefix.len() + encoded.len()].copy_from_slice(encoded);
+            prefix.len() + encoded.len()
+        } else {
+            buffer[prefix.len()..prefix.len() + SHA256_SIZE].copy_from_slice(&sha256(encoded)?);
+            prefix.len() + SHA256_SIZE
+        };
+        Ok(DerivedKey { buffer, size })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.size]
+    }
+}
+
+/// Persistent mapping from keys of type K to values of type V
+///
+/// Key and value encodings must fit within MAX_ENCODED_SIZE.
+pub struct StorageMap<K: ?Sized, V> {
+    prefix: &'static [u8],
+    _types: PhantomData<fn(&K) -> V>,
+}
+
+impl<K: ?Sized, V> StorageMap<K, V> {
+    pub const fn new(prefix: &'static [u8]) -> StorageMap<K, V> {
+        assert!(prefix.len() <= MAX_PREFIX_SIZE, "collection prefix is longer than MAX_PREFIX_SIZE");
+        assert!(writable_prefix(prefix), "collection prefix is empty or reserved");
+        StorageMap {
+            prefix,
+            _types: PhantomData,
+        }
+    }
+
+    pub fn prefix(&self) -> &'static [u8] {
+        self.prefix
+    }
+}
+
+impl<K: CostackEncode + ?Sized, V: CostackEncode + CostackDecode> StorageMap<K, V> {
+    /// The value stored for `key`, or None if there is none
+    pub fn get(&self, key: &K) -> Result<Option<V>, SystemError> {
+        load_value(DerivedKey::new(self.prefix, key)?.as_bytes())
+    }
+
+    /// Store `value` for `key`, replacing any previous value
+    pub fn insert(&self, key: &K, value: &V) -> Result<(), SystemError> {
+        store_value(DerivedKey::new(self.prefix, key)?.as_bytes(), value)
+    }
+
+    /// Remove the value stored for `key`. Removing a missing key is not an error.
+    pub fn remove(&self, key: &K) -> Result<(), SystemError> {
+        delete_state(DerivedKey::new(self.prefix, key)?.as_bytes())
+    }
+
+    pub fn contains(&self, key: &K) -> Result<bool, SystemError> {
+        key_exists(DerivedKey::new(self.prefix, key)?.as_bytes())
+    }
+}
+
+impl<K: CostackEncode + ?Sized, V: CostackEncode + CostackDecode + Default> StorageMap<K, V> {
+    /// The value stored for `key`, or the default value of V if there is none
+    pub fn get_or_default(&self, key: &K) -> Result<V, SystemError> {
+        Ok(self.get(key)?.unwrap_or_default())
+    }
+
+    /// Load the value for `key`, or the default value of V if there is none, modify it with `f` and store it again
+    pub fn update<R>(&self, key: &K, f: impl FnOnce(&mut V) -> R) -> Result<R, SystemError> {
+        let mut value = self.get_or_default(key)?;
+        let result = f(&mut value);
+        self.insert(key, &value)?;
+        Ok(result)
+    }
+}
+
+#[derive(core::fmt::Debug)]
+pub enum CollectionError {
+    /// The index is not below the length of the collection
+    OutOfBounds,
+    System(SystemError),
+}
+
+impl From<SystemError> for CollectionError {
+    fn from(error: SystemError) -> CollectionError {
+        CollectionError::System(error)
+    }
+}
+
+// Sequences store their length or bounds under their prefix followed by nothing, and their entries under their prefix
+// followed by the encoded u64 position, so the two can never share a key
+fn load_entry<T: CostackDecode>(prefix: &[u8], position: u64) -> Result<T, SystemError> {
+    match load_value(DerivedKey::new(prefix, &position)?.as_bytes())? {
+        Some(value) => Ok(value),
+        // An entry within the bounds of a sequence must exist
+        None => Err(SystemError::UnexpectedSize),
+    }
+}
+
+fn store_entry<T: CostackEncode>(prefix: &[u8], position: u64, value: &T) -> Result<(), SystemError> {
+    store_value(DerivedKey::new(prefix, &position)?.as_bytes(), value)
+}
+
+fn delete_entry(prefix: &[u8], position: u64) -> Result<(), SystemError> {
+    delete_state(DerivedKey::new(prefix, &position)?.as_bytes())
+}
+
+/// Persistent growable sequence of values of type T
+pub struct StorageVec<T> {
+    prefix: &'static [u8],
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> StorageVec<T> {
+    pub const fn new(prefix: &'static [u8]) -> StorageVec<T> {
+        assert!(prefix.len() <= MAX_PREFIX_SIZE, "collection prefix is longer than MAX_PREFIX_SIZE");
+        assert!(writable_prefix(prefix), "collection prefix is empty or reserved");
+        StorageVec {
+            prefix,
+            _value: PhantomData,
+        }
+    }
+
+    pub fn prefix(&self) -> &'static [u8] {
+        self.prefix
+    }
+
+    pub fn len(&self) -> Result<u64, SystemError> {
+        Ok(load_value(DerivedKey::new(self.prefix, &())?.as_bytes())?.unwrap_or(0))
+    }
+
+    pub fn is_empty(&self) -> Result<bool, SystemError> {
+        Ok(self.len()? == 0)
+    }
+
+    fn set_len(&self, len: u64) -> Result<(), SystemError> {
+        store_value(DerivedKey::new(self.prefix, &())?.as_bytes(), &len)
+    }
+}
+
+impl<T: CostackEncode + CostackDecode> StorageVec<T> {
+    /// The value at `index`, or None if it is out of bounds
+    pub fn get(&self, index: u64) -> Result<Option<T>, SystemError> {
+        if index >= self.len()? {
+            return Ok(None);
+        }
+        load_entry(self.prefix, index).map(Some)
+    }
+
+    /// Replace the value at `index`
+    pub fn set(&self, index: u64, value: &T) -> Result<(), CollectionError> {
+        if index >= self.len()? {
+            return Err(CollectionError::OutOfBounds);
+        }
+        Ok(store_entry(self.prefix, index, value)?)
+    }
+
+    pub fn push(&self, value: &T) -> Result<(), SystemError> {
+        let len = self.len()?;
+        store_entry(self.prefix, len, value)?;
+        self.set_len(len + 1)
+    }
+
+    /// Remove and return the last value, or None if empty
+    pub fn pop(&self) -> Result<Option<T>, SystemError> {
+        let len = self.len()?;
+        if len == 0 {
+            return Ok(None);
+        }
+        let value = load_entry(self.prefix, len - 1)?;
+        delete_entry(self.prefix, len - 1)?;
+        self.set_len(len - 1)?;
+        Ok(Some(value))
+    }
+
+    /// Remove and return the value at `index`, replacing it with the last value. This doesn't keep the order, but is O(1).
+    pub fn swap_remove(&self, index: u64) -> Result<T, CollectionError> {
+        let len = self.len()?;
+        if index >= len {
+            return Err(CollectionError::OutOfBounds);
+        }
+        let value = load_entry(self.prefix, index)?;
+        if index != len - 1 {
+            let last: T = load_entry(self.prefix, len - 1)?;
+            store_entry(self.prefix, index, &last)?;
+        }
+        delete_entry(self.prefix, len - 1)?;
+        self.set_len(len - 1)?;
+        Ok(value)
+    }
+}
+
+neutron_struct! {
+    #[derive(Default)]
+    struct DequeBounds {
+        /// Position of the first value
+        head: u64,
+        /// Position after the last value
+        tail: u64,
+    }
+}
+
+impl DequeBounds {
+    fn len(&self) -> u64 {
+        self.tail.wrapping_sub(self.head)
+    }
+}
+
+/// Persistent double-ended queue of values of type T
+///
+/// Values are stored at positions between a head and a tail index, which both move as values are added and removed,
+/// so the entries don't have to be shifted when the front of the queue changes.
+pub struct StorageDeque<T> {
+    prefix: &'static [u8],
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> StorageDeque<T> {
+    pub const fn new(prefix: &'static [u8]) -> StorageDeque<T> {
+        assert!(prefix.len() <= MAX_PREFIX_SIZE, "collection prefix is longer than MAX_PREFIX_SIZE");
+        assert!(writable_prefix(prefix), "collection prefix is empty or reserved");
+        StorageDeque {
+            prefix,
+            _value: PhantomData,
+        }
+    }
+
+    pub fn prefix(&self) -> &'static [u8] {
+        self.prefix
+    }
+
+    pub fn len(&self) -> Result<u64, SystemError> {
+        Ok(self.bounds()?.len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool, SystemError> {
+        Ok(self.len()? == 0)
+    }
+
+    fn bounds(&self) -> Result<DequeBounds, SystemError> {
+        Ok(load_value(DerivedKey::new(self.prefix, &())?.as_bytes())?.unwrap_or_default())
+    }
+
+    fn set_bounds(&self, bounds: &DequeBounds) -> Result<(), SystemError> {
+        store_value(DerivedKey::new(self.prefix, &())?.as_bytes(), bounds)
+    }
+}
+
+impl<T: CostackEncode + CostackDecode> StorageDeque<T> {
+    /// The value at `index` counted from the front, or None if it is out of bounds
+    pub fn get(&self, index: u64) -> Result<Option<T>, SystemError> {
+        let bounds = self.bounds()?;
+        if index >= bounds.len() {
+            return Ok(None);
+        }
+        load_entry(self.prefix, bounds.head.wrapping_add(index)).map(Some)
+    }
+
+    /// Replace the value at `index` counted from the front
+    pub fn set(&self, index: u64, value: &T) -> Result<(), CollectionError> {
+        let bounds = self.bounds()?;
+        if index >= bounds.len() {
+            return Err(CollectionError::OutOfBounds);
+        }
+        Ok(store_entry(self.prefix, bounds.head.wrapping_add(index), value)?)
+    }
+
+    pub fn front(&self) -> Result<Option<T>, SystemError> {
+        self.get(0)
+    }
+
+    pub fn back(&self) -> Result<Option<T>, SystemError> {
+        let len = self.len()?;
+        if len == 0 {
+            return Ok(None);
+        }
+        self.get(len - 1)
+    }
+
+    pub fn push_back(&self, value: &T) -> Result<(), SystemError> {
+        let mut bounds = self.bounds()?;
+        store_entry(self.prefix, bounds.tail, value)?;
+        bounds.tail = bounds.tail.wrapping_add(1);
+        self.set_bounds(&bounds)
+    }
+
+    pub fn push_front(&self, value: &T) -> Result<(), SystemError> {
+        let mut bounds = self.bounds()?;
+        bounds.head = bounds.head.wrapping_sub(1);
+        store_entry(self.prefix, bounds.head, value)?;
+        self.set_bounds(&bounds)
+    }
+
+    /// Remove and return the first value, or None if empty
+    pub fn pop_front(&self) -> Result<Option<T>, SystemError> {
+        let mut bounds = self.bounds()?;
+        if bounds.len() == 0 {
+            return Ok(None);
+        }
+        let value = load_entry(self.prefix, bounds.head)?;
+        delete_entry(self.prefix, bounds.head)?;
+        bounds.head = bounds.head.wrapping_add(1);
+        self.set_bounds(&bounds)?;
+        Ok(Some(value))
+    }
+
+    /// Remove and return the last value, or None if empty
+    pub fn pop_back(&self) -> Result<Option<T>, SystemError> {
+        let mut bounds = self.bounds()?;
+        if bounds.len() == 0 {
+            return Ok(None);
+        }
+        bounds.tail = bounds.tail.wrapping_sub(1);
+        let value = load_entry(self.prefix, bounds.tail)?;
+        delete_entry(self.prefix, bounds.tail)?;
+        self.set_bounds(&bounds)?;
+        Ok(Some(value))
+    }
+}
+
+// Tags appended to the prefix of an EnumerableStorageMap for each of the collections it is made of
+const VALUES_TAG: u8 = b'v';
+const POSITIONS_TAG: u8 = b'p';
+const KEYS_TAG: u8 = b'k';
+
+fn tagged_prefix(prefix: &[u8], tag: u8) -> ([u8; MAX_PREFIX_SIZE], usize) {
+    let mut buffer = [0; MAX_PREFIX_SIZE];
+    buffer[..prefix.len()].copy_from_slice(prefix);
+    buffer[prefix.len()] = tag;
+    (buffer, prefix.len() + 1)
+}
+
+/// Persistent mapping which keeps an index of its keys, so that its entries can be listed
+///
+/// Next to the values, every key is kept in a list together with its position in that list.
+/// Removing an entry moves the last key of the list into the freed position, so removal is O(1) but doesn't keep the order.
+/// Entries removed while paginating can therefore cause the last entry to be skipped by a later page.
+pub struct EnumerableStorageMap<K, V> {
+    prefix: &'static [u8],
+    _types: PhantomData<fn(&K) -> V>,
+}
+
+impl<K, V> EnumerableStorageMap<K, V> {
+    pub const fn new(prefix: &'static [u8]) -> EnumerableStorageMap<K, V> {
+        // One byte of the prefix is used to tell apart the values, positions and keys
+        assert!(
+            prefix.len() < MAX_PREFIX_SIZE,
+            "collection prefix is not shorter than MAX_PREFIX_SIZE"
+        );
+        assert!(writable_prefix(prefix), "collection prefix is empty or reserved");
+        EnumerableStorageMap {
+            prefix,
+            _types: PhantomData,
+        }
+    }
+
+    pub fn prefix(&self) -> &'static [u8] {
+        self.prefix
+    }
+
+    /// The number of entries
+    pub fn len(&self) -> Result<u64, SystemError> {
+        let (keys, size) = tagged_prefix(self.prefix, KEYS_TAG);
+        Ok(load_value(DerivedKey::new(&keys[..size], &())?.as_bytes())?.unwrap_or(0))
+    }
+
+    pub fn is_empty(&self) -> Result<bool, SystemError> {
+        Ok(self.len()? == 0)
+    }
+
+    fn set_len(&self, len: u64) -> Result<(), SystemError> {
+        let (keys, size) = tagged_prefix(self.prefix, KEYS_TAG);
+        store_value(DerivedKey::new(&keys[..size], &())?.as_bytes(), &len)
+    }
+}
+
+impl<K: CostackEncode + CostackDecode, V: CostackEncode + CostackDecode> EnumerableStorageMap<K, V> {
+    pub fn get(&self, key: &K) -> Result<Option<V>, SystemError> {
+        let (values, size) = tagged_prefix(self.prefix, VALUES_TAG);
+        load_value(DerivedKey::new(&values[..size], key)?.as_bytes())
+    }
+
+    pub fn contains(&self, key: &K) -> Result<bool, SystemError> {
+        Ok(self.position(key)?.is_some())
+    }
+
+    /// Store `value` for `key`, adding the key to the index if it is new
+    pub fn insert(&self, key: &K, value: &V) -> Result<(), SystemError> {
+        if self.position(key)?.is_none() {
+            let len = self.len()?;
+            let (keys, size) = tagged_prefix(self.prefix, KEYS_TAG);
+            store_entry(&keys[..size], len, key)?;
+            self.set_position(key, len)?;
+            self.set_len(len + 1)?;
+        }
+        let (values, size) = tagged_prefix(self.prefix, VALUES_TAG);
+        store_value(DerivedKey::new(&values[..size], key)?.as_bytes(), value)
+    }
+
+    /// Remove the entry for `key`. Removing a missing key is not an error.
+    pub fn remove(&self, key: &K) -> Result<(), SystemError> {
+        let position = match self.position(key)? {
+            Some(position) => position,
+            None => return Ok(()),
+        };
+        let len = self.len()?;
+        let (keys, keys_size) = tagged_prefix(self.prefix, KEYS_TAG);
+        let keys = &keys[..keys_size];
+        if position != len - 1 {
+            let last: K = load_entry(keys, len - 1)?;
+            store_entry(keys, position, &last)?;
+            self.set_position(&last, position)?;
+        }
+        delete_entry(keys, len - 1)?;
+        self.set_len(len - 1)?;
+        let (positions, size) = tagged_prefix(self.prefix, POSITIONS_TAG);
+        delete_state(DerivedKey::new(&positions[..size], key)?.as_bytes())?;
+        let (values, size) = tagged_prefix(self.prefix, VALUES_TAG);
+        delete_state(DerivedKey::new(&values[..size], key)?.as_bytes())
+    }
+
+    /// The key at `index` of the index, or None if it is out of bounds
+    pub fn key_at(&self, index: u64) -> Result<Option<K>, SystemError> {
+        if index >= self.len()? {
+            return Ok(None);
+        }
+        self.load_key(index).map(Some)
+    }
+
+    /// Iterate over at most `limit` entries, starting at index `cursor`
+    ///
+    /// Pass 0 as the cursor for the first page, and the `next_cursor` of the previous page for the following ones.
+    pub fn iter_from(&self, cursor: u64, limit: u64) -> EnumerableIter<'_, K, V> {
+        EnumerableIter {
+            map: self,
+            index: cursor,
+            remaining: limit,
+            len: None,
+            gas_guard: None,
+            stopped: false,
+        }
+    }
+
+    // The key at an index known to be within bounds
+    fn load_key(&self, index: u64) -> Result<K, SystemError> {
+        let (keys, size) = tagged_prefix(self.prefix, KEYS_TAG);
+        load_entry(&keys[..size], index)
+    }
+
+    fn position(&self, key: &K) -> Result<Option<u64>, SystemError> {
+        let (positions, size) = tagged_prefix(self.prefix, POSITIONS_TAG);
+        load_value(DerivedKey::new(&positions[..size], key)?.as_bytes())
+    }
+
+    fn set_position(&self, key: &K, position: u64) -> Result<(), SystemError> {
+        let (positions, size) = tagged_prefix(self.prefix, POSITIONS_TAG);
+        store_value(DerivedKey::new(&positions[..size], key)?.as_bytes(), &position)
+    }
+}
+
+/// One page of the entries of an `EnumerableStorageMap`
+///
+/// The number of entries is loaded once for the page, so the map must not be modified while the page is iterated.
+pub struct EnumerableIter<'a, K, V> {
+    map: &'a EnumerableStorageMap<K, V>,
+    index: u64,
+    remaining: u64,
+    /// The number of entries, once loaded by the first item of the page
+    len: Option<u64>,
+    gas_guard: Option<GasGuard>,
+    stopped: bool,
+}
+
+impl<'a, K, V> EnumerableIter<'a, K, V> {
+    /// Also end the page early once the gas left falls to the margin of `guard`
+    pub fn with_gas_guard(mut self, guard: GasGuard) -> EnumerableIter<'a, K, V> {
+        self.gas_guard = Some(guard);
+        self
+    }
+
+    /// True if the page ended because of the gas guard rather than the limit or the end of the map
+    pub fn stopped_early(&self) -> bool {
+        self.stopped
+    }
+
+    /// The cursor for the next page, or None once the whole map was iterated
+    pub fn next_cursor(&self) -> Result<Option<u64>, SystemError> {
+        let len = match self.len {
+            Some(len) => len,
+            None => self.map.len()?,
+        };
+        if self.index < len {
+            Ok(Some(self.index))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<'a, K: CostackEncode + CostackDecode, V: CostackEncode + CostackDecode> Iterator for EnumerableIter<'a, K, V> {
+    type Item = Result<(K, V), SystemError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 || self.stopped {
+            return None;
+        }
+        if let Some(guard) = &self.gas_guard {
+            if !guard.has_gas() {
+                self.stopped = true;
+                return None;
+            }
+        }
+        let len = match self.len {
+            Some(len) => len,
+            None => match self.map.len() {
+                Ok(len) => {
+                    self.len = Some(len);
+                    len
+                }
+                Err(error) => return Some(Err(error)),
+            },
+        };
+        if self.index >= len {
+            return None;
+        }
+        let key = match self.map.load_key(self.index) {
+            Ok(key) => key,
+            Err(error) => return Some(Err(error)),
+        };
+        let value = match self.map.get(&key) {
+            Ok(Some(value)) => value,
+            // Every indexed key must have a value
+            Ok(None) => return Some(Err(SystemError::UnexpectedSize)),
+            Err(error) => return Some(Err(error)),
+        };
+        self.index += 1;
+        self.remaining -= 1;
+        Some(Ok((key, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+    use std::vec::Vec;
+
+    #[test]
+    fn short_keys_are_used_directly() {
+        let _serial = mock::serial();
+        let key = [7; SHA256_SIZE - 1];
+        let derived = DerivedKey::new(b"map", &key[..]).unwrap();
+        assert_eq!(derived.as_bytes()[..3], *b"map");
+        assert_eq!(derived.as_bytes()[3..], key);
+        assert!(mock::calls().is_empty());
+    }
+
+    #[test]
+    fn long_keys_are_hashed() {
+        let _serial = mock::serial();
+        let key = [7; SHA256_SIZE];
+        let derived = DerivedKey::new(b"map", &key[..]).unwrap();
+        assert_eq!(derived.as_bytes()[..3], *b"map");
+        assert_eq!(derived.as_bytes()[3..], mock::digest(&key)[..]);
+        // The sha256 function of the hashing element
+        assert_eq!(mock::calls(), [(10, 1)]);
+    }
+
+    #[test]
+    fn encoded_keys_are_measured_after_encoding() {
+        let _serial = mock::serial();
+        // A u64 key is encoded into 8 bytes, and a key at the boundary is hashed like a byte key
+        assert_eq!(DerivedKey::new(b"map", &7u64).unwrap().as_bytes()[3..], 7u64.to_le_bytes());
+        let key = [7; SHA256_SIZE];
+        assert_eq!(
+            DerivedKey::new(b"map", &key).unwrap().as_bytes(),
+            DerivedKey::new(b"map", &key[..]).unwrap().as_bytes()
+        );
+    }
+
+    #[test]
+    fn maps_store_long_keys_under_their_hash() {
+        let _serial = mock::serial();
+        let map: StorageMap<[u8], u64> = StorageMap::new(b"map");
+        let long = [7; 40];
+        map.insert(&long[..], &1).unwrap();
+        map.insert(&long[..SHA256_SIZE - 1], &2).unwrap();
+        let mut hashed = b"map".to_vec();
+        hashed.extend_from_slice(&mock::digest(&long));
+        assert_eq!(mock::stored(&hashed), Some(1u64.to_le_bytes().to_vec()));
+        let mut direct = b"map".to_vec();
+        direct.extend_from_slice(&long[..SHA256_SIZE - 1]);
+        assert_eq!(mock::stored(&direct), Some(2u64.to_le_bytes().to_vec()));
+        assert_eq!(map.get(&long[..]).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn prefixes_longer_than_the_maximum_are_rejected() {
+        let _serial = mock::serial();
+        assert!(matches!(
+            DerivedKey::new(&[1; MAX_PREFIX_SIZE + 1], &7u64),
+            Err(SystemError::UnexpectedSize)
+        ));
+    }
+
+    #[test]
+    fn swap_remove_moves_the_last_value() {
+        let _serial = mock::serial();
+        let vec: StorageVec<u64> = StorageVec::new(b"vec");
+        for value in 10..14 {
+            vec.push(&value).unwrap();
+        }
+        assert_eq!(vec.swap_remove(1).unwrap(), 11);
+        assert_eq!(vec.len().unwrap(), 3);
+        assert_eq!(vec.get(1).unwrap(), Some(13));
+        assert_eq!(vec.get(3).unwrap(), None);
+        assert_eq!(mock::stored(DerivedKey::new(b"vec", &3u64).unwrap().as_bytes()), None);
+
+        // Removing the last value doesn't move anything
+        assert_eq!(vec.swap_remove(2).unwrap(), 12);
+        assert_eq!(vec.get(0).unwrap(), Some(10));
+        assert_eq!(vec.get(1).unwrap(), Some(13));
+        assert!(matches!(vec.swap_remove(2), Err(CollectionError::OutOfBounds)));
+        assert_eq!(vec.len().unwrap(), 2);
+    }
+
+    #[test]
+    fn deque_head_wraps_around() {
+        let _serial = mock::serial();
+        let deque: StorageDeque<u64> = StorageDeque::new(b"deque");
+        deque.push_front(&2).unwrap();
+        deque.push_front(&1).unwrap();
+        deque.push_back(&3).unwrap();
+        // The head moved below position 0 and wrapped around to the end of the u64 range
+        assert!(mock::stored(DerivedKey::new(b"deque", &u64::MAX).unwrap().as_bytes()).is_some());
+        assert!(mock::stored(DerivedKey::new(b"deque", &(u64::MAX - 1)).unwrap().as_bytes()).is_some());
+        assert_eq!(deque.len().unwrap(), 3);
+        assert_eq!(deque.get(0).unwrap(), Some(1));
+        assert_eq!(deque.get(1).unwrap(), Some(2));
+        assert_eq!(deque.get(2).unwrap(), Some(3));
+        assert_eq!(deque.get(3).unwrap(), None);
+
+        assert_eq!(deque.pop_back().unwrap(), Some(3));
+        assert_eq!(deque.pop_back().unwrap(), Some(2));
+        assert_eq!(deque.back().unwrap(), Some(1));
+        assert_eq!(deque.pop_front().unwrap(), Some(1));
+        assert_eq!(deque.pop_front().unwrap(), None);
+        assert!(deque.is_empty().unwrap());
+    }
+
+    #[test]
+    fn remove_moves_the_last_key() {
+        let _serial = mock::serial();
+        let map: EnumerableStorageMap<u64, u64> = EnumerableStorageMap::new(b"map");
+        for key in 1..5 {
+            map.insert(&key, &(key * 10)).unwrap();
+        }
+        map.remove(&2).unwrap();
+        assert_eq!(map.len().unwrap(), 3);
+        assert_eq!(map.key_at(1).unwrap(), Some(4));
+        assert_eq!(map.key_at(3).unwrap(), None);
+        assert_eq!(map.get(&2).unwrap(), None);
+        assert!(!map.contains(&2).unwrap());
+
+        // The moved key is found at its new position when removed in turn
+        map.remove(&4).unwrap();
+        assert_eq!(map.key_at(1).unwrap(), Some(3));
+        assert_eq!(map.len().unwrap(), 2);
+        let entries: Vec<(u64, u64)> = map.iter_from(0, 10).map(Result::unwrap).collect();
+        assert_eq!(entries, [(1, 10), (3, 30)]);
+        // Removing a missing key is not an error
+        map.remove(&2).unwrap();
+        assert_eq!(map.len().unwrap(), 2);
+    }
+
+    #[test]
+    fn pages_load_the_length_once() {
+        let _serial = mock::serial();
+        let map: EnumerableStorageMap<u64, u64> = EnumerableStorageMap::new(b"map");
+        for key in 0..5 {
+            map.insert(&key, &key).unwrap();
+        }
+        mock::clear_calls();
+        let mut page = map.iter_from(1, 3);
+        let keys: Vec<u64> = page.by_ref().map(|entry| entry.unwrap().0).collect();
+        assert_eq!(keys, [1, 2, 3]);
+        // The length, then the key and value of every entry
+        assert_eq!(mock::calls().len(), 1 + 2 * 3);
+        assert_eq!(page.next_cursor().unwrap(), Some(4));
+
+        let mut last = map.iter_from(4, 3);
+        assert_eq!(last.by_ref().count(), 1);
+        assert_eq!(last.next_cursor().unwrap(), None);
+    }
+}
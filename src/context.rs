@@ -0,0 +1,141 @@
+//! Information about the current execution and the environment it runs in
+
+/*
+## Execution Context
+
+ID: 5
+
+Functions:
+
+* self_address() -> (address)
+* sender() -> (address)
+* origin() -> (address)
+* gas_remaining() -> (u64)
+* gas_limit() -> (u64)
+* gas_price() -> (u64)
+* execution_mode() -> (u8)
+* call_depth() -> (u32)
+
+The sender is the address which directly called the current contract, which may be another contract.
+The origin is the address which signed the transaction, and so is never a contract.
+
+execution_mode is 1 for a regular call, 2 for a static call which can't modify state, and 3 while deploying a contract.
+call_depth is 0 for the contract called directly by the transaction, and increases by 1 for each nested contract call.
+
+## Block Context
+
+ID: 6
+
+Functions:
+
+* block_height() -> (u64)
+* block_timestamp() -> (u64)
+* median_time() -> (u64)
+* block_hash(height) -> (hash)
+* block_creator() -> (address)
+* difficulty() -> (u64)
+* chain_id() -> (u32)
+
+All information is about the block which is currently being created, except for block_hash.
+Only hashes of previous blocks are available, block_hash fails for the current block or any later height.
+Times are in seconds since the unix epoch. median_time is the median timestamp of the previous 11 blocks,
+which unlike the block timestamp can not be moved backwards, and so is the safer choice for time-locks.
+*/
+
+extern crate neutron_common;
+
+use neutron_common::*;
+
+use crate::codec::*;
+use crate::syscalls::*;
+
+pub(crate) enum ExecutionContextFunctions {
+    SelfAddress = 1,
+    Sender,
+    Origin,
+    GasRemaining,
+    GasLimit,
+    GasPrice,
+    ExecutionMode,
+    CallDepth,
+}
+
+#[derive(Clone, Copy, PartialEq, core::fmt::Debug)]
+pub enum ExecutionMode {
+    Call,
+    /// A call which can't modify state, such as a view call from another contract or an off-chain query
+    Static,
+    /// The constructor of a contract which is being deployed
+    Deploy,
+}
+
+impl CostackDecode for ExecutionMode {
+    const ABI_VALUE: u32 = ABI_VALUE_U8;
+    const MAX_SIZE: usize = 1;
+
+    fn decode(bytes: &[u8]) -> Result<Self, RecoverableError> {
+        match bytes {
+            [1] => Ok(ExecutionMode::Call),
+            [2] => Ok(ExecutionMode::Static),
+            [3] => Ok(ExecutionMode::Deploy),
+            [] => Err(RecoverableError::StackItemTooSmall),
+            _ => Err(RecoverableError::StackItemTooLarge), // TODO: Replace with neutron-star error
+        }
+    }
+}
+
+pub(crate) enum BlockContextFunctions {
+    BlockHeight = 1,
+    BlockTimestamp,
+    MedianTime,
+    BlockHash,
+    BlockCreator,
+    Difficulty,
+    ChainId,
+}
+
+pub const BLOCK_HASH_SIZE: usize = 32;
+
+system_element! {
+    pub(crate) const EXECUTION_CONTEXT_ELEMENT = 5;
+
+    /// The address of the currently executing contract
+    pub fn self_address() -> NeutronAddress = ExecutionContextFunctions::SelfAddress as u32;
+    /// The address which directly called the current contract
+    pub fn sender() -> NeutronAddress = ExecutionContextFunctions::Sender as u32;
+    /// The address which signed the transaction being executed
+    pub fn origin() -> NeutronAddress = ExecutionContextFunctions::Origin as u32;
+    /// Whether the current execution is a regular call, a static call or a deployment
+    pub fn execution_mode() -> ExecutionMode = ExecutionContextFunctions::ExecutionMode as u32;
+    /// The number of contract calls which led to the current execution, 0 if called directly by a transaction
+    pub fn call_depth() -> u32 = ExecutionContextFunctions::CallDepth as u32;
+}
+
+system_element! {
+    pub(crate) const BLOCK_CONTEXT_ELEMENT = 6;
+
+    /// The height of the block currently being created
+    pub fn block_height() -> u64 = BlockContextFunctions::BlockHeight as u32;
+    /// The timestamp of the block currently being created, as chosen by its creator
+    pub fn block_timestamp() -> u64 = BlockContextFunctions::BlockTimestamp as u32;
+    /// The median timestamp of the previous blocks, which always increases
+    pub fn median_time() -> u64 = BlockContextFunctions::MedianTime as u32;
+    /// The hash of a previous block
+    pub fn block_hash(height: u64) -> [u8; BLOCK_HASH_SIZE] = BlockContextFunctions::BlockHash as u32;
+    /// The address which created the current block
+    pub fn block_creator() -> NeutronAddress = BlockContextFunctions::BlockCreator as u32;
+    /// The difficulty of the current block, in compact form
+    pub fn difficulty() -> u64 = BlockContextFunctions::Difficulty as u32;
+    /// The identifier of the chain, used to tell apart mainnet and testnets
+    pub fn chain_id() -> u32 = BlockContextFunctions::ChainId as u32;
+}
+
+/// True if the current contract was called by another contract rather than directly by a transaction
+pub fn is_contract_caller() -> Result<bool, SystemError> {
+    Ok(sender()? != origin()?)
+}
+
+/// True while running the constructor of a contract which is being deployed
+pub fn is_deploying() -> Result<bool, SystemError> {
+    Ok(execution_mode()? == ExecutionMode::Deploy)
+}
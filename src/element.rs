@@ -0,0 +1,220 @@
+//! Declarative definition of hypervisor element wrappers
+//!
+//! Every element wrapper follows the same pattern: check that the element is available,
+//! push the inputs in reverse order so the element pops them in the declared order, make the system call,
+//! and pop the outputs. `system_element!` generates that code from a declaration of the element and its functions:
+//!
+//! ```ignore
+//! system_element! {
+//!     /// Element ID of global storage
+//!     pub const GLOBAL_STORAGE_ELEMENT = 1;
+//!
+//!     /// Store a value under a key
+//!     pub fn store_state(key: &[u8], value: &[u8]) = 1;
+//!     /// Check if any value is stored under a key
+//!     pub fn key_exists(key: &[u8]) -> bool = 3;
+//! }
+//! ```
+//!
+//! Inputs must implement `CostackEncode`, or be `CostackItems` to push every item of a slice separately.
+//! The output, if any, must implement `CostackDecode`, or be a tuple of such types for elements pushing several outputs,
+//! which are popped in the order of the tuple. Functions with an output return it once popped and decoded, failing
+//! with `SystemError::UnexpectedSize` if the element did not push valid values. Functions without an output return
+//! the system call result.
+//!
+//! Elements taking a variable number of items, such as logging, expect the caller to push those items before calling
+//! the wrapper. Ending the inputs with `..count`, where `count` is one of the inputs, declares that `count` items
+//! were already pushed:
+//!
+//! ```ignore
+//! system_element! {
+//!     element LOGGING_ELEMENT;
+//!
+//!     fn element_log_info(count: u8, ..count) = 2;
+//! }
+//! ```
+//!
+//! The costack is left as it was before the items were pushed whenever a wrapper fails: already pushed items
+//! are discarded if the element is not available, and if any output can't be popped the remaining outputs are discarded.
+//!
+//! An element declared elsewhere can be extended with more functions by starting with `element NAME;` instead.
+//! Function IDs are checked at compile time to not be 0, which is reserved for `element_available`,
+//! and to be unique within the declaration.
+
+use crate::codec::*;
+use crate::syscalls::*;
+
+/// An input of an element function
+pub trait ElementInput {
+    fn push_input(&self);
+}
+
+impl<T: CostackEncode + ?Sized> ElementInput for T {
+    fn push_input(&self) {
+        self.push_costack();
+    }
+}
+
+/// An element input made up of one costack item for every value of the slice, so that the element pops them in order
+pub struct CostackItems<'a, T>(pub &'a [T]);
+
+impl<'a, T: CostackEncode> ElementInput for CostackItems<'a, T> {
+    fn push_input(&self) {
+        for item in self.0.iter().rev() {
+            item.push_costack();
+        }
+    }
+}
+
+/// The output of an element function, made up of `ITEMS` costack items
+pub trait ElementOutput: Sized {
+    const ITEMS: usize;
+
+    /// Pop and decode the output, leaving no output items on the costack if it fails
+    fn pop_output() -> Result<Self, SystemError>;
+}
+
+impl<T: CostackDecode> ElementOutput for T {
+    const ITEMS: usize = 1;
+
+    fn pop_output() -> Result<Self, SystemError> {
+        T::pop_costack().map_err(|_| SystemError::UnexpectedSize)
+    }
+}
+
+/// Discard `count` costack items
+#[doc(hidden)]
+pub fn discard_items(count: usize) {
+    for _ in 0..count {
+        discard_costack();
+    }
+}
+
+/// Fail with ElementUnavailable unless the hypervisor provides an element, discarding `pushed` items if it doesn't
+#[doc(hidden)]
+pub fn require_element_or_discard(element: u32, pushed: usize) -> Result<(), SystemError> {
+    let result = require_element(element);
+    if result.is_err() {
+        discard_items(pushed);
+    }
+    result
+}
+
+macro_rules! impl_element_output_tuple {
+    ($($TYPE:ident),+) => {
+        impl<$($TYPE: CostackDecode),+> ElementOutput for ($($TYPE,)+) {
+            const ITEMS: usize = [$(stringify!($TYPE)),+].len();
+
+            fn pop_output() -> Result<Self, SystemError> {
+                let mut remaining = Self::ITEMS;
+                Ok(($({
+                    remaining -= 1;
+                    match $TYPE::pop_costack() {
+                        Ok(value) => value,
+                        Err(_) => {
+                            discard_items(remaining);
+                            return Err(SystemError::UnexpectedSize);
+                        }
+                    }
+                },)+))
+            }
+        }
+    };
+}
+
+impl_element_output_tuple!(A, B);
+impl_element_output_tuple!(A, B, C);
+impl_element_output_tuple!(A, B, C, D);
+
+/// Check at compile time that function IDs are valid and unique
+#[doc(hidden)]
+pub const fn valid_function_ids(ids: &[u32]) -> bool {
+    let mut i = 0;
+    while i < ids.len() {
+        if ids[i] == 0 {
+            return false;
+        }
+        let mut j = i + 1;
+        while j < ids.len() {
+            if ids[i] == ids[j] {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_push_reversed {
+    () => {};
+    ($FIRST:ident $(, $REST:ident)*) => {
+        $crate::__neutron_push_reversed!($($REST),*);
+        $crate::element::ElementInput::push_input(&$FIRST);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_element_output {
+    () => {
+        u32
+    };
+    ($OUTPUT:ty) => {
+        $OUTPUT
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_element_pop {
+    ($RESULT:ident) => {
+        Ok($RESULT)
+    };
+    ($RESULT:ident, $OUTPUT:ty) => {
+        <$OUTPUT as $crate::element::ElementOutput>::pop_output()
+    };
+}
+
+/// Declare a hypervisor element and generate safe wrappers for its functions. See the `element` module.
+#[macro_export]
+macro_rules! system_element {
+    (
+        $(#[$ELEMENT_META:meta])*
+        $ELEMENT_VIS:vis const $ELEMENT:ident = $ELEMENT_ID:expr;
+        $($FUNCTIONS:tt)*
+    ) => {
+        $(#[$ELEMENT_META])*
+        $ELEMENT_VIS const $ELEMENT: u32 = $ELEMENT_ID;
+
+        $crate::system_element! {
+            element $ELEMENT;
+            $($FUNCTIONS)*
+        }
+    };
+    (
+        element $ELEMENT:path;
+        $(
+            $(#[$META:meta])*
+            $VIS:vis fn $NAME:ident($($ARG:ident : $ARG_TYPE:ty),* $(, ..$PUSHED:ident)? $(,)?) $(-> $OUTPUT:ty)? = $FUNCTION:expr;
+        )*
+    ) => {
+        const _: () = assert!(
+            $crate::element::valid_function_ids(&[$($FUNCTION),*]),
+            "element function IDs must be unique and not 0"
+        );
+
+        $(
+            $(#[$META])*
+            $VIS fn $NAME($($ARG: $ARG_TYPE),*)
+                -> Result<$crate::__neutron_element_output!($($OUTPUT)?), $crate::syscalls::SystemError> {
+                $crate::element::require_element_or_discard($ELEMENT, 0 $(+ $PUSHED as usize)?)?;
+                $crate::__neutron_push_reversed!($($ARG),*);
+                let _result = $crate::syscalls::_system_call($ELEMENT, $FUNCTION)?;
+                $crate::__neutron_element_pop!(_result $(, $OUTPUT)?)
+            }
+        )*
+    };
+}
@@ -0,0 +1,233 @@
+//! Structured events which can be indexed off-chain
+//!
+//! An event carries up to MAX_TOPICS topic hashes, which indexers can filter on, and any number of data items.
+//! Events declared with `neutron_event!` use the hash of their signature as the first topic,
+//! followed by the hash of each field marked `#[indexed]`. Every field, indexed or not, is included in the data.
+//! The signature hash is computed like a method selector, from the event name and the ABI names of the field types.
+
+/*
+## Events
+
+ID: 9
+
+Functions:
+
+* emit(topic_count, topic, ..., data_count, data, ...)
+
+Topics are u32 hashes. Similar to logging, data items are popped in reverse order,
+so the data item pushed last is the first one popped after data_count.
+*/
+
+use crate::codec::*;
+use crate::element::*;
+use crate::interface::*;
+use crate::storage::*;
+use crate::syscalls::*;
+
+enum EventsFunctions {
+    Emit = 1,
+}
+
+system_element! {
+    const EVENTS_ELEMENT = 9;
+
+    // The data items are pushed by the caller
+    fn element_emit(topic_count: u8, topics: CostackItems<'_, u32>, data_count: u8, ..data_count) = EventsFunctions::Emit as u32;
+}
+
+/// The largest number of topics an event can have, including the signature topic
+pub const MAX_TOPICS: usize = 4;
+/// The largest number of `#[indexed]` fields an event declared with `neutron_event!` can have
+pub const MAX_INDEXED_FIELDS: usize = MAX_TOPICS - 1;
+
+/// ABI manifest entry describing an event
+#[derive(core::fmt::Debug)]
+pub struct EventInfo {
+    pub name: &'static str,
+    /// Hash of the event signature, used as the first topic
+    pub signature: u32,
+    pub fields: &'static [EventField],
+}
+
+#[derive(core::fmt::Debug)]
+pub struct EventField {
+    pub name: &'static str,
+    pub indexed: bool,
+}
+
+pub trait Event {
+    const INFO: EventInfo;
+
+    fn emit(&self) -> Result<(), SystemError>;
+}
+
+/// Hash of an encoded value, used as the topic for indexed fields
+pub fn topic_hash<T: CostackEncode + ?Sized>(value: &T) -> u32 {
+    let mut buffer = [0; MAX_ENCODED_SIZE];
+    let size = value.encode(&mut buffer);
+    fnv1a(&buffer[..size])
+}
+
+/// Emit an event with a single data item. Not allowed in view mode.
+pub fn emit(topic_hashes: &[u32], data: &[u8]) -> Result<(), SystemError> {
+    push_costack(data);
+    emit_from_costack(topic_hashes, 1)
+}
+
+/// Emit an event using the top `data_count` costack items as data. Not allowed in view mode.
+pub fn emit_from_costack(topic_hashes: &[u32], data_count: u8) -> Result<(), SystemError> {
+    let checked = if is_view_mode() {
+        Err(SystemError::WriteInView)
+    } else if topic_hashes.len() > MAX_TOPICS {
+        Err(SystemError::UnexpectedSize)
+    } else {
+        Ok(())
+    };
+    if let Err(error) = checked {
+        // The data items would otherwise be left on the costack
+        discard_items(data_count as usize);
+        return Err(error);
+    }
+    element_emit(topic_hashes.len() as u8, CostackItems(topic_hashes), data_count)?;
+    Ok(())
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_is_indexed {
+    () => {
+        false
+    };
+    (#[indexed] $($REST:tt)*) => {
+        true
+    };
+    (#$ATTR:tt $($REST:tt)*) => {
+        $crate::__neutron_is_indexed!($($REST)*)
+    };
+}
+
+// The #[indexed] modifier is not a real attribute, so it is removed from every field before emitting the struct.
+// Each field is given as `{ [kept attributes] remaining attributes and field }`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_event_struct {
+    ([$(#[$META:meta])* $VIS:vis struct $NAME:ident] [$($FIELDS:tt)*]) => {
+        $(#[$META])*
+        $VIS struct $NAME {
+            $($FIELDS)*
+        }
+    };
+    ($HEADER:tt [$($FIELDS:tt)*] { [$($KEPT:tt)*] #[indexed] $($FIELD:tt)* } $($REST:tt)*) => {
+        $crate::__neutron_event_struct!{$HEADER [$($FIELDS)*] { [$($KEPT)*] $($FIELD)* } $($REST)*}
+    };
+    ($HEADER:tt [$($FIELDS:tt)*] { [$($KEPT:tt)*] #$ATTR:tt $($FIELD:tt)* } $($REST:tt)*) => {
+        $crate::__neutron_event_struct!{$HEADER [$($FIELDS)*] { [$($KEPT)* #$ATTR] $($FIELD)* } $($REST)*}
+    };
+    ($HEADER:tt [$($FIELDS:tt)*] { [$($KEPT:tt)*] $($FIELD:tt)* } $($REST:tt)*) => {
+        $crate::__neutron_event_struct!{$HEADER [$($FIELDS)* $($KEPT)* $($FIELD)*,] $($REST)*}
+    };
+}
+
+/// Declare an event struct and implement `Event` for it
+///
+/// ```ignore
+/// neutron_event! {
+///     pub struct Transfer {
+///         #[indexed]
+///         pub from: NeutronAddress,
+///         #[indexed]
+///         pub to: NeutronAddress,
+///         /// Amount of coins transferred
+///         pub amount: u64,
+///     }
+/// }
+/// ```
+///
+/// Field types must implement `CostackEncode`. Fields marked `#[indexed]` are also emitted as topics.
+#[macro_export]
+macro_rules! neutron_event {
+    (
+        $(#[$META:meta])*
+        $VIS:vis struct $NAME:ident {
+            $(
+                $(#$FIELD_ATTR:tt)*
+                $FIELD_VIS:vis $FIELD:ident : $FIELD_TYPE:ty
+            ),* $(,)?
+        }
+    ) => {
+        $crate::__neutron_event_struct! {
+            [$(#[$META])* $VIS struct $NAME] []
+            $({ [] $(#$FIELD_ATTR)* $FIELD_VIS $FIELD: $FIELD_TYPE })*
+        }
+
+        const _: () = assert!(
+            0 $(+ $crate::__neutron_is_indexed!($(#$FIELD_ATTR)*) as usize)* <= $crate::events::MAX_INDEXED_FIELDS,
+            "too many indexed event fields"
+        );
+
+        impl $crate::events::Event for $NAME {
+            const INFO: $crate::events::EventInfo = $crate::events::EventInfo {
+                name: stringify!($NAME),
+                signature: $crate::interface::method_selector(
+                    stringify!($NAME),
+                    &[$(<$FIELD_TYPE as $crate::codec::CostackEncode>::ABI_NAME),*],
+                ),
+                fields: &[$($crate::events::EventField {
+                    name: stringify!($FIELD),
+                    indexed: $crate::__neutron_is_indexed!($(#$FIELD_ATTR)*),
+                },)*],
+            };
+
+            fn emit(&self) -> Result<(), $crate::syscalls::SystemError> {
+                let mut topics = [0; $crate::events::MAX_TOPICS];
+                topics[0] = Self::INFO.signature;
+                let mut topic_count = 1;
+                $(
+                    if $crate::__neutron_is_indexed!($(#$FIELD_ATTR)*) {
+                        topics[topic_count] = $crate::events::topic_hash(&self.$FIELD);
+                        topic_count += 1;
+                    }
+                )*
+                let mut data_count: u8 = 0;
+                $(
+                    $crate::codec::CostackEncode::push_costack(&self.$FIELD);
+                    data_count += 1;
+                )*
+                $crate::events::emit_from_costack(&topics[..topic_count], data_count)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NeutronAddress;
+
+    neutron_event! {
+        /// Coins moved between two addresses
+        #[derive(Clone, Copy)]
+        pub struct Transfer {
+            /// The address the coins are taken from
+            #[indexed]
+            pub from: NeutronAddress,
+            #[indexed]
+            /// The address the coins are given to
+            pub(crate) to: NeutronAddress,
+            amount: u64,
+        }
+    }
+
+    #[test]
+    fn fields_accept_attributes_and_visibility() {
+        let fields = Transfer::INFO.fields;
+        assert_eq!(fields.len(), 3);
+        assert!(fields[0].name == "from" && fields[0].indexed);
+        assert!(fields[1].name == "to" && fields[1].indexed);
+        assert!(fields[2].name == "amount" && !fields[2].indexed);
+        assert_eq!(
+            Transfer::INFO.signature,
+            crate::interface::method_selector("Transfer", &["address", "address", "u64"])
+        );
+    }
+}
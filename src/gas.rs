@@ -0,0 +1,135 @@
+//! Gas introspection and helpers for bounding work by the gas which is left
+//!
+//! Loops over user supplied data can run out of gas part way through, reverting everything done so far.
+//! `GasGuard` stops such loops early while a margin of gas is still left, so that progress can be saved,
+//! for instance with a `GasCursor`, and continued in a later call.
+
+use crate::context::*;
+use crate::storage::*;
+use crate::syscalls::*;
+
+system_element! {
+    element EXECUTION_CONTEXT_ELEMENT;
+
+    /// The amount of gas left for the current execution
+    pub fn gas_remaining() -> u64 = ExecutionContextFunctions::GasRemaining as u32;
+    /// The amount of gas the current execution started with
+    pub fn gas_limit() -> u64 = ExecutionContextFunctions::GasLimit as u32;
+    /// The price paid per unit of gas by the current transaction
+    pub fn gas_price() -> u64 = ExecutionContextFunctions::GasPrice as u32;
+}
+
+/// Checks whether enough gas is left to keep doing work
+pub struct GasGuard {
+    margin: u64,
+}
+
+impl GasGuard {
+    /// `margin` is the amount of gas to keep for the work done after stopping, such as saving progress
+    pub fn new(margin: u64) -> GasGuard {
+        GasGuard { margin }
+    }
+
+    /// True while more gas than the margin is left
+    ///
+    /// On a hypervisor without the execution context element the remaining gas can't be checked, so this is always true
+    /// and work is only bounded by the gas limit, as without a guard. Any other failure to read the gas counts as false.
+    pub fn has_gas(&self) -> bool {
+        match gas_remaining() {
+            Ok(remaining) => remaining > self.margin,
+            Err(SystemError::ElementUnavailable) => true,
+            Err(_) => false,
+        }
+    }
+
+    /// Wrap an iterator so that it ends early once the margin is reached
+    pub fn limit<I: Iterator>(&self, iter: I) -> GasBounded<I> {
+        GasBounded {
+            iter,
+            margin: self.margin,
+            stopped: false,
+        }
+    }
+}
+
+/// Iterator which ends when the gas left falls to the margin of the `GasGuard` which created it
+pub struct GasBounded<I> {
+    iter: I,
+    margin: u64,
+    stopped: bool,
+}
+
+impl<I> GasBounded<I> {
+    /// True if iteration ended because of the gas margin rather than by running out of items
+    pub fn stopped_early(&self) -> bool {
+        self.stopped
+    }
+}
+
+impl<I: Iterator> Iterator for GasBounded<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        if self.stopped {
+            return None;
+        }
+        if !GasGuard::new(self.margin).has_gas() {
+            self.stopped = true;
+            return None;
+        }
+        self.iter.next()
+    }
+}
+
+/// Position of a resumable iteration, persisted in storage under a fixed key
+pub struct GasCursor {
+    position: StorageValue<u64>,
+}
+
+impl GasCursor {
+    pub const fn new(key: &'static [u8]) -> GasCursor {
+        GasCursor {
+            position: StorageValue::new(key),
+        }
+    }
+
+    /// The saved position, or 0 if nothing was saved yet
+    pub fn load(&self) -> Result<u64, SystemError> {
+        self.position.get_or_default()
+    }
+
+    pub fn save(&self, position: u64) -> Result<(), SystemError> {
+        self.position.set(&position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::*;
+    use crate::mock;
+
+    // The mock doesn't provide the execution context element
+    #[test]
+    fn unavailable_gas_is_unbounded() {
+        let _serial = mock::serial();
+        let guard = GasGuard::new(1000);
+        assert!(guard.has_gas());
+        let mut bounded = guard.limit(0..5);
+        assert_eq!(bounded.by_ref().count(), 5);
+        assert!(!bounded.stopped_early());
+    }
+
+    #[test]
+    fn guarded_pages_are_complete_without_gas_introspection() {
+        let _serial = mock::serial();
+        let map: EnumerableStorageMap<u64, u64> = EnumerableStorageMap::new(b"map");
+        for key in 0..3 {
+            map.insert(&key, &key).unwrap();
+        }
+        let mut page = map.iter_from(0, 10).with_gas_guard(GasGuard::new(1000));
+        assert_eq!(page.by_ref().count(), 3);
+        assert!(!page.stopped_early());
+        assert_eq!(page.next_cursor().unwrap(), None);
+    }
+}
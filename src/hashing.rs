@@ -0,0 +1,23 @@
+//! Cryptographic hashing provided by the hypervisor
+
+/*
+## Hashing
+
+ID: 10
+
+Functions:
+
+* sha256(data) -> (hash)
+*/
+
+enum HashingFunctions {
+    Sha256 = 1,
+}
+
+pub const SHA256_SIZE: usize = 32;
+
+system_element! {
+    const HASHING_ELEMENT = 10;
+
+    pub fn sha256(data: &[u8]) -> [u8; SHA256_SIZE] = HashingFunctions::Sha256 as u32;
+}
@@ -0,0 +1,330 @@
+//! Typed contract interfaces
+//!
+//! `neutron_interface!` declares a trait describing the public methods of a contract.
+//! From the same declaration it generates a caller struct for invoking those methods on another contract,
+//! a `dispatch` method for contracts implementing the trait, and a manifest listing the methods.
+//!
+//! Every argument is stored in the comap under the name of the parameter, and the return value is stored
+//! in the result comap under `RETURN_KEY`.
+//! The method to call is identified by a 4 byte selector stored under `SELECTOR_KEY`.
+//! For debugging, the method can instead be given by name under `METHOD_KEY`, which is only checked when no selector is present.
+
+use crate::coins::*;
+use crate::syscalls::*;
+
+/// Comap key holding the selector of the method being called
+pub const SELECTOR_KEY: &str = "!selector";
+/// Comap key holding the name of the method being called, used when no selector is given
+pub const METHOD_KEY: &str = "!method";
+/// Comap key holding the return value of the method which was called
+pub const RETURN_KEY: &str = "!return";
+/// The longest method name which can be dispatched
+pub const MAX_METHOD_NAME_SIZE: usize = 64;
+
+/// Manifest entry describing a single interface method
+#[derive(core::fmt::Debug)]
+pub struct MethodInfo {
+    pub name: &'static str,
+    pub selector: u32,
+    /// The method can not write state, so it is safe to simulate
+    pub view: bool,
+    /// The method accepts coins sent along with the call
+    pub payable: bool,
+}
+
+#[derive(core::fmt::Debug)]
+pub enum DispatchError {
+    /// No method was given, or it does not match any method of the interface
+    UnknownMethod,
+    /// The named argument is missing or has the wrong type
+    InvalidArgument(&'static str),
+    /// Coins were sent to a method which is not payable
+    NotPayable,
+    System(SystemError),
+}
+
+/// Reject the call if coins were sent to a method which is not payable.
+/// Without the value transfer element no coins can be sent, so every method is accepted.
+pub fn check_payable(payable: bool) -> Result<(), DispatchError> {
+    if payable {
+        return Ok(());
+    }
+    match value_received() {
+        Ok(0) | Err(SystemError::ElementUnavailable) => Ok(()),
+        Ok(_) => Err(DispatchError::NotPayable),
+        Err(error) => Err(DispatchError::System(error)),
+    }
+}
+
+const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// Continue a 32 bit FNV-1a hash with more bytes
+pub const fn fnv1a_continue(mut hash: u32, bytes: &[u8]) -> u32 {
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(FNV_PRIME);
+        i += 1;
+    }
+    hash
+}
+
+/// 32 bit FNV-1a hash, as used for selectors and event topics
+pub const fn fnv1a(bytes: &[u8]) -> u32 {
+    fnv1a_continue(FNV_OFFSET_BASIS, bytes)
+}
+
+/// Compute the selector of a method from its name and parameter types, as FNV-1a of `name(type1,type2)`.
+/// Parameter types are given by their `CostackEncode::ABI_NAME`, such as `u64`, `address`, `bytes` or `bytes32`,
+/// so the selector doesn't depend on how a type is written and can be computed off-chain.
+pub const fn method_selector(name: &str, parameter_types: &[&str]) -> u32 {
+    let mut hash = fnv1a(name.as_bytes());
+    hash = fnv1a_continue(hash, b"(");
+    let mut i = 0;
+    while i < parameter_types.len() {
+        if i > 0 {
+            hash = fnv1a_continue(hash, b",");
+        }
+        hash = fnv1a_continue(hash, parameter_types[i].as_bytes());
+        i += 1;
+    }
+    fnv1a_continue(hash, b")")
+}
+
+/// Check at compile time that no two methods of an interface share a selector
+#[doc(hidden)]
+pub const fn unique_selectors(selectors: &[u32]) -> bool {
+    let mut i = 0;
+    while i < selectors.len() {
+        let mut j = i + 1;
+        while j < selectors.len() {
+            if selectors[i] == selectors[j] {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Identifies the method being called
+pub enum MethodId<'a> {
+    Selector(u32),
+    Name(&'a [u8]),
+}
+
+impl<'a> MethodId<'a> {
+    pub fn matches(&self, name: &str, selector: u32) -> bool {
+        match self {
+            MethodId::Selector(s) => *s == selector,
+            MethodId::Name(n) => *n == name.as_bytes(),
+        }
+    }
+}
+
+/// Write the selector of the method to call into the outgoing comap
+pub fn write_selector(selector: u32) {
+    write_comap_u32(SELECTOR_KEY, selector);
+}
+
+/// Write the name of the method to call into the outgoing comap. Only used when no selector is written.
+pub fn write_method_name(name: &str) {
+    write_comap_array_u8(METHOD_KEY, name.as_bytes());
+}
+
+/// Read the method being called from the input comap, preferring the selector over the name
+pub fn read_method_id(name_buffer: &mut [u8; MAX_METHOD_NAME_SIZE]) -> Result<MethodId<'_>, DispatchError> {
+    if let Ok(selector) = read_comap_u32(SELECTOR_KEY) {
+        return Ok(MethodId::Selector(selector));
+    }
+    match read_comap_fixed_array_u8(METHOD_KEY, name_buffer) {
+        Ok(size) if size as usize <= MAX_METHOD_NAME_SIZE => Ok(MethodId::Name(&name_buffer[..size as usize])),
+        _ => Err(DispatchError::UnknownMethod),
+    }
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_return_type {
+    () => {
+        ()
+    };
+    ($RETURN:ty) => {
+        $RETURN
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_selector {
+    ($METHOD:ident($($ARG_TYPE:ty),*)) => {{
+        const SELECTOR: u32 = $crate::interface::method_selector(stringify!($METHOD), &[$(<$ARG_TYPE as $crate::codec::CostackEncode>::ABI_NAME),*]);
+        SELECTOR
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_is_payable {
+    () => {
+        false
+    };
+    (#[payable] $($REST:tt)*) => {
+        true
+    };
+    (#$ATTR:tt $($REST:tt)*) => {
+        $crate::__neutron_is_payable!($($REST)*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_is_view {
+    () => {
+        false
+    };
+    (#[view] $($REST:tt)*) => {
+        true
+    };
+    (#$ATTR:tt $($REST:tt)*) => {
+        $crate::__neutron_is_view!($($REST)*)
+    };
+}
+
+// Method modifiers such as #[view] and #[payable] are not real attributes, so they are removed before emitting an item
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_strip_modifiers {
+    ([$($KEPT:tt)*] #[view] $($REST:tt)*) => {
+        $crate::__neutron_strip_modifiers!{[$($KEPT)*] $($REST)*}
+    };
+    ([$($KEPT:tt)*] #[payable] $($REST:tt)*) => {
+        $crate::__neutron_strip_modifiers!{[$($KEPT)*] $($REST)*}
+    };
+    ([$($KEPT:tt)*] #$ATTR:tt $($REST:tt)*) => {
+        $crate::__neutron_strip_modifiers!{[$($KEPT)* #$ATTR] $($REST)*}
+    };
+    ([$($KEPT:tt)*] $($ITEM:tt)*) => {
+        $($KEPT)* $($ITEM)*
+    };
+}
+
+/// Declare a contract interface
+///
+/// ```ignore
+/// neutron_interface! {
+///     #[caller(TokenCaller, events(Transfer))]
+///     pub trait Token {
+///         fn transfer(&self, to: NeutronAddress, amount: u64) -> bool;
+///         #[payable]
+///         fn buy(&self) -> u64;
+///         #[view]
+///         fn total_supply(&self) -> u64;
+///     }
+/// }
+/// ```
+///
+/// Argument and return types must implement `CostackEncode` and `CostackDecode`.
+/// Interfaces in which two methods have the same selector are rejected at compile time.
+/// The optional `events` list names the `Event` types emitted by the interface, and is only used for the manifest.
+/// Methods marked `#[view]` are dispatched in view mode, so any attempt to write state fails with `SystemError::WriteInView`,
+/// as do calls to other contracts unless the whole execution is static.
+/// Only methods marked `#[payable]` accept coins, calls to other methods which send coins fail with `DispatchError::NotPayable`.
+#[macro_export]
+macro_rules! neutron_interface {
+    (
+        #[caller($CALLER:ident $(, events($($EVENT:ty),* $(,)?))?)]
+        $(#[$TRAIT_META:meta])*
+        $VIS:vis trait $TRAIT:ident {
+            $(
+                $(#$METHOD_ATTR:tt)*
+                fn $METHOD:ident(&self $(, $ARG:ident : $ARG_TYPE:ty)* $(,)?) $(-> $RETURN:ty)?;
+            )*
+        }
+    ) => {
+        const _: () = assert!(
+            $crate::interface::unique_selectors(&[$($crate::__neutron_selector!($METHOD($($ARG_TYPE),*))),*]),
+            "interface methods have colliding selectors"
+        );
+
+        $(#[$TRAIT_META])*
+        $VIS trait $TRAIT {
+            $(
+                $crate::__neutron_strip_modifiers! {
+                    [] $(#$METHOD_ATTR)*
+                    fn $METHOD(&self $(, $ARG: $ARG_TYPE)*) $(-> $RETURN)?;
+                }
+            )*
+
+            /// Call the method named in the input comap and write its return value to the outgoing comap
+            fn dispatch(&self) -> Result<(), $crate::interface::DispatchError> {
+                let mut name_buffer = [0; $crate::interface::MAX_METHOD_NAME_SIZE];
+                let method = $crate::interface::read_method_id(&mut name_buffer)?;
+                $(
+                    if method.matches(stringify!($METHOD), $crate::__neutron_selector!($METHOD($($ARG_TYPE),*))) {
+                        $crate::interface::check_payable($crate::__neutron_is_payable!($(#$METHOD_ATTR)*))?;
+                        let view_guard = $crate::storage::ViewGuard::enter_if($crate::__neutron_is_view!($(#$METHOD_ATTR)*));
+                        let result: $crate::__neutron_return_type!($($RETURN)?) = self.$METHOD($(
+                            $crate::codec::read_comap::<$ARG_TYPE>(stringify!($ARG))
+                                .map_err(|_| $crate::interface::DispatchError::InvalidArgument(stringify!($ARG)))?
+                        ),*);
+                        drop(view_guard);
+                        $crate::codec::write_comap($crate::interface::RETURN_KEY, &result);
+                        $crate::cache::flush().map_err($crate::interface::DispatchError::System)?;
+                        return Ok(());
+                    }
+                )*
+                Err($crate::interface::DispatchError::UnknownMethod)
+            }
+        }
+
+        /// Caller for invoking the methods of the interface on a deployed contract
+        $VIS struct $CALLER {
+            pub address: $crate::NeutronAddress,
+            pub gas_limit: u64,
+            pub value: u64,
+        }
+
+        impl $CALLER {
+            /// Manifest of all methods in the interface
+            pub const MANIFEST: &'static [$crate::interface::MethodInfo] = &[
+                $($crate::interface::MethodInfo {
+                    name: stringify!($METHOD),
+                    selector: $crate::__neutron_selector!($METHOD($($ARG_TYPE),*)),
+                    view: $crate::__neutron_is_view!($(#$METHOD_ATTR)*),
+                    payable: $crate::__neutron_is_payable!($(#$METHOD_ATTR)*),
+                },)*
+            ];
+
+            /// Manifest of the events emitted by the interface
+            pub const EVENTS: &'static [$crate::events::EventInfo] = &[
+                $($(<$EVENT as $crate::events::Event>::INFO,)*)?
+            ];
+
+            pub fn new(address: $crate::NeutronAddress, gas_limit: u64) -> $CALLER {
+                $CALLER { address, gas_limit, value: 0 }
+            }
+
+            /// Set the value to send along with each call
+            pub fn with_value(mut self, value: u64) -> $CALLER {
+                self.value = value;
+                self
+            }
+
+            $(
+                $crate::__neutron_strip_modifiers! {
+                    [] $(#$METHOD_ATTR)*
+                    pub fn $METHOD(&self $(, $ARG: $ARG_TYPE)*)
+                        -> Result<$crate::__neutron_return_type!($($RETURN)?), $crate::call::CallError> {
+                        $crate::interface::write_selector($crate::__neutron_selector!($METHOD($($ARG_TYPE),*)));
+                        $($crate::codec::write_comap(stringify!($ARG), &$ARG);)*
+                        let result = $crate::call::call_contract(&self.address, self.gas_limit, self.value)?;
+                        result.read($crate::interface::RETURN_KEY).map_err($crate::call::CallError::InvalidResult)
+                    }
+                }
+            )*
+        }
+    };
+}
@@ -0,0 +1,369 @@
+//! Transactional storage scopes
+//!
+//! Writes made inside `transaction` are buffered in a journal instead of being stored, and are only stored once the
+//! closure returns `Ok`. If it returns `Err`, its writes are discarded. Reads inside a transaction see its buffered writes.
+//! Transactions can be nested, in which case committing an inner transaction only moves its writes to the outer one.
+//!
+//! The journal has a fixed capacity of JOURNAL_CAPACITY written keys across all open transactions, see `transaction`.
+//! Keys longer than MAX_JOURNAL_KEY_SIZE and values longer than MAX_JOURNAL_VALUE_SIZE can't be buffered,
+//! and fail with SystemError::UnexpectedSize.
+//! Other contracts called from within a transaction don't see its uncommitted writes.
+//!
+//! If storing the writes of the outermost transaction fails after some of them were already stored,
+//! the execution is reverted, as those writes can't be undone.
+
+extern crate neutron_star_rt;
+
+use core::cell::UnsafeCell;
+
+use neutron_star_rt::__revert_execution;
+
+use crate::codec::MAX_ENCODED_SIZE;
+use crate::collections::MAX_DERIVED_KEY_SIZE;
+use crate::storage::*;
+use crate::syscalls::*;
+
+/// Enough for a batch of 60 transfers, each in its own nested transaction, see `transaction`
+pub const JOURNAL_CAPACITY: usize = 64;
+pub const MAX_JOURNAL_KEY_SIZE: usize = MAX_DERIVED_KEY_SIZE;
+pub const MAX_JOURNAL_VALUE_SIZE: usize = MAX_ENCODED_SIZE;
+
+// Same status as a contract panic: fault + error + revert
+const PARTIAL_COMMIT_STATUS: u32 = 8 + 1 + 2;
+
+#[derive(Clone, Copy)]
+struct JournalEntry {
+    /// Depth of the transaction which owns the write
+    depth: u32,
+    deleted: bool,
+    key: [u8; MAX_JOURNAL_KEY_SIZE],
+    key_size: usize,
+    value: [u8; MAX_JOURNAL_VALUE_SIZE],
+    value_size: usize,
+}
+
+impl JournalEntry {
+    const EMPTY: JournalEntry = JournalEntry {
+        depth: 0,
+        deleted: false,
+        key: [0; MAX_JOURNAL_KEY_SIZE],
+        key_size: 0,
+        value: [0; MAX_JOURNAL_VALUE_SIZE],
+        value_size: 0,
+    };
+
+    fn key(&self) -> &[u8] {
+        &self.key[..self.key_size]
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.value[..self.value_size]
+    }
+}
+
+struct Journal {
+    /// Number of transactions currently open
+    depth: u32,
+    entries: [JournalEntry; JOURNAL_CAPACITY],
+    len: usize,
+}
+
+impl Journal {
+    /// The newest write to `key` made by any open transaction
+    fn latest(&self, key: &[u8]) -> Option<&JournalEntry> {
+        self.entries[..self.len].iter().rev().find(|entry| entry.key() == key)
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.entries.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+    }
+}
+
+struct JournalCell(UnsafeCell<Journal>);
+
+// Contracts are single threaded, and the journal is only borrowed within a single call to `with_journal`
+unsafe impl Sync for JournalCell {}
+
+static JOURNAL: JournalCell = JournalCell(UnsafeCell::new(Journal {
+    depth: 0,
+    entries: [JournalEntry::EMPTY; JOURNAL_CAPACITY],
+    len: 0,
+}));
+
+fn with_journal<R>(f: impl FnOnce(&mut Journal) -> R) -> R {
+    unsafe { f(&mut *JOURNAL.0.get()) }
+}
+
+/// True while inside a transaction
+pub fn in_transaction() -> bool {
+    with_journal(|journal| journal.depth > 0)
+}
+
+/// Run `f` as a transaction, storing its writes only if it returns `Ok`
+///
+/// **At most JOURNAL_CAPACITY (64) keys can be written** by all open transactions together.
+/// Writing more fails with SystemError::JournalFull, which `f` should return so that the transaction is rolled back.
+/// A nested transaction writing a key which an outer one already wrote takes another entry until it commits,
+/// after which only one entry is kept. So a batch transfer running every item in a nested transaction
+/// needs one entry for the sender, one per recipient, and one more while an item is running,
+/// which fits 60 items with room to spare. Collections write several keys per change: `StorageVec::push` writes
+/// 2 keys and `EnumerableStorageMap::insert` up to 4. Larger batches have to be split into several transactions.
+pub fn transaction<T, E: From<SystemError>>(f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    let start = with_journal(|journal| {
+        journal.depth += 1;
+        journal.len
+    });
+    let result = f();
+    let depth = with_journal(|journal| {
+        journal.depth -= 1;
+        journal.depth
+    });
+    match result {
+        Ok(value) => {
+            commit(start, depth)?;
+            Ok(value)
+        }
+        Err(error) => {
+            with_journal(|journal| journal.len = start);
+            Err(error)
+        }
+    }
+}
+
+/// Hand the writes from `start` over to the transaction at `depth`, or store them if no transaction is left
+fn commit(start: usize, depth: u32) -> Result<(), SystemError> {
+    if depth > 0 {
+        with_journal(|journal| {
+            let mut index = start;
+            while index < journal.len {
+                journal.entries[index].depth = depth;
+                // An earlier write of the outer transaction to the same key is replaced by this one
+                let key_size = journal.entries[index].key_size;
+                let key = journal.entries[index].key;
+                match journal.entries[..index]
+                    .iter()
+                    .position(|entry| entry.depth == depth && entry.key() == &key[..key_size])
+                {
+                    Some(earlier) => journal.remove(earlier),
+                    None => index += 1,
+                }
+            }
+        });
+        return Ok(());
+    }
+    // Entries are applied oldest first, so later writes to a key win
+    let len = with_journal(|journal| journal.len);
+    for index in 0..len {
+        let entry = with_journal(|journal| journal.entries[index]);
+        let value = if entry.deleted { None } else { Some(entry.value()) };
+        if let Err(error) = apply_write(entry.key(), value) {
+            if index > 0 {
+                // The earlier writes are already stored, and returning an error would look like a rollback
+                unsafe { __revert_execution(PARTIAL_COMMIT_STATUS) }
+            }
+            with_journal(|journal| journal.len = 0);
+            return Err(error);
+        }
+    }
+    with_journal(|journal| journal.len = 0);
+    Ok(())
+}
+
+/// Close all transactions and discard their writes
+#[cfg(test)]
+pub(crate) fn reset() {
+    with_journal(|journal| {
+        journal.depth = 0;
+        journal.len = 0;
+    });
+}
+
+/// Buffer a write, or None to delete. Returns false if no transaction is open and the write should be applied directly.
+pub(crate) fn record(key: &[u8], value: Option<&[u8]>) -> Result<bool, SystemError> {
+    with_journal(|journal| {
+        if journal.depth == 0 {
+            return Ok(false);
+        }
+        if key.len() > MAX_JOURNAL_KEY_SIZE || value.is_some_and(|value| value.len() > MAX_JOURNAL_VALUE_SIZE) {
+            return Err(SystemError::UnexpectedSize);
+        }
+        let depth = journal.depth;
+        let existing = journal.entries[..journal.len]
+            .iter()
+            .position(|entry| entry.depth == depth && entry.key() == key);
+        let index = match existing {
+            Some(index) => index,
+            None if journal.len < JOURNAL_CAPACITY => {
+                journal.len += 1;
+                journal.len - 1
+            }
+            None => return Err(SystemError::JournalFull),
+        };
+        let entry = &mut journal.entries[index];
+        entry.depth = depth;
+        entry.key[..key.len()].copy_from_slice(key);
+        entry.key_size = key.len();
+        match value {
+            Some(value) => {
+                entry.deleted = false;
+                entry.value[..value.len()].copy_from_slice(value);
+                entry.value_size = value.len();
+            }
+            None => {
+                entry.deleted = true;
+                entry.value_size = 0;
+            }
+        }
+        Ok(true)
+    })
+}
+
+/// Load a buffered write, or None if no open transaction wrote to the key
+pub(crate) fn load(key: &[u8], buffer: &mut [u8]) -> Option<LoadOutcome> {
+    with_journal(|journal| {
+        let entry = journal.latest(key)?;
+        if entry.deleted {
+            return Some(LoadOutcome::Missing);
+        }
+        Some(copy_loaded(entry.value(), buffer))
+    })
+}
+
+/// Whether a key exists according to the buffered writes, or None if no open transaction wrote to the key
+pub(crate) fn exists(key: &[u8]) -> Option<bool> {
+    with_journal(|journal| journal.latest(key).map(|entry| !entry.deleted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache;
+    use crate::mock;
+    use std::format;
+    use std::vec::Vec;
+
+    fn load(key: &[u8]) -> Option<Vec<u8>> {
+        let mut buffer = [0; 16];
+        match load_state_fixed(key, &mut buffer).unwrap() {
+            LoadOutcome::Missing => None,
+            LoadOutcome::Complete { len } => Some(buffer[..len].to_vec()),
+            LoadOutcome::Truncated { .. } => panic!("value is larger than the test buffer"),
+        }
+    }
+
+    fn journal_len() -> usize {
+        with_journal(|journal| journal.len)
+    }
+
+    #[test]
+    fn inner_rollback_restores_outer_value() {
+        let _serial = mock::serial();
+        transaction(|| {
+            store_state(b"key", b"outer")?;
+            let inner: Result<(), SystemError> = transaction(|| {
+                store_state(b"key", b"inner")?;
+                store_state(b"other", b"inner")?;
+                assert_eq!(load(b"key"), Some(b"inner".to_vec()));
+                Err(SystemError::UnexpectedSize)
+            });
+            assert!(inner.is_err());
+            assert_eq!(load(b"key"), Some(b"outer".to_vec()));
+            assert_eq!(load(b"other"), None);
+            assert_eq!(journal_len(), 1);
+            Ok::<(), SystemError>(())
+        })
+        .unwrap();
+        assert_eq!(mock::stored(b"key"), Some(b"outer".to_vec()));
+        assert_eq!(mock::stored(b"other"), None);
+    }
+
+    #[test]
+    fn inner_commit_merges_into_outer() {
+        let _serial = mock::serial();
+        transaction(|| {
+            store_state(b"key", b"outer")?;
+            transaction(|| {
+                store_state(b"key", b"inner")?;
+                store_state(b"other", b"inner")
+            })?;
+            // The earlier write of the outer transaction is replaced
+            assert_eq!(journal_len(), 2);
+            assert_eq!(load(b"key"), Some(b"inner".to_vec()));
+            assert!(mock::calls().is_empty());
+            Ok::<(), SystemError>(())
+        })
+        .unwrap();
+        assert_eq!(mock::stored(b"key"), Some(b"inner".to_vec()));
+        assert_eq!(mock::stored(b"other"), Some(b"inner".to_vec()));
+        assert_eq!(journal_len(), 0);
+    }
+
+    #[test]
+    fn outer_rollback_discards_committed_inner() {
+        let _serial = mock::serial();
+        let result: Result<(), SystemError> = transaction(|| {
+            transaction(|| store_state(b"key", b"inner"))?;
+            Err(SystemError::UnexpectedSize)
+        });
+        assert!(result.is_err());
+        assert_eq!(mock::stored(b"key"), None);
+        assert_eq!(load(b"key"), None);
+    }
+
+    #[test]
+    fn commit_writes_through_cache() {
+        let _serial = mock::serial();
+        cache::enable();
+        transaction(|| {
+            store_state(b"key", b"value")?;
+            delete_state(b"deleted")
+        })
+        .unwrap();
+        assert!(mock::calls().is_empty());
+        assert_eq!(load(b"key"), Some(b"value".to_vec()));
+        cache::flush().unwrap();
+        assert_eq!(mock::stored(b"key"), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn capacity_overflow_is_journal_full() {
+        let _serial = mock::serial();
+        let result: Result<(), SystemError> = transaction(|| {
+            for i in 0..JOURNAL_CAPACITY {
+                store_state(format!("key{}", i).as_bytes(), b"value")?;
+            }
+            // Writing a key again doesn't take another entry
+            store_state(b"key0", b"again")?;
+            store_state(b"one more", b"value")
+        });
+        assert!(matches!(result, Err(SystemError::JournalFull)));
+        assert_eq!(journal_len(), 0);
+        assert!(mock::calls().is_empty());
+    }
+
+    #[test]
+    fn batch_transfer_fits() {
+        let _serial = mock::serial();
+        const ITEMS: u64 = 60;
+        transaction(|| {
+            for item in 0..ITEMS {
+                // Every item is a transfer from the same sender, and the last one fails after writing
+                let result: Result<(), SystemError> = transaction(|| {
+                    store_state(b"sender", &(ITEMS - item).to_le_bytes())?;
+                    store_state(format!("recipient{}", item).as_bytes(), &item.to_le_bytes())?;
+                    if item == ITEMS - 1 {
+                        return Err(SystemError::UnexpectedSize);
+                    }
+                    Ok(())
+                });
+                assert_eq!(result.is_ok(), item < ITEMS - 1);
+            }
+            Ok::<(), SystemError>(())
+        })
+        .unwrap();
+        assert_eq!(mock::stored(b"sender"), Some(2u64.to_le_bytes().to_vec()));
+        assert_eq!(mock::stored(b"recipient58"), Some(58u64.to_le_bytes().to_vec()));
+        assert_eq!(mock::stored(b"recipient59"), None);
+    }
+}
@@ -0,0 +1,226 @@
+//! Namespaced storage layouts
+//!
+//! All contract state shares one flat key space, so two libraries writing to the same key would overwrite each other.
+//! `storage_layout!` declares a struct of storage fields, such as `StorageValue` or `StorageMap`, and gives each field
+//! a key prefix derived from the hash of its namespace and name. Duplicate prefixes within a layout are rejected at compile time,
+//! and `unique_prefixes` can be used to check several layouts against each other:
+//!
+//! ```ignore
+//! storage_layout! {
+//!     #[namespace = "token"]
+//!     pub struct TokenStorage {
+//!         pub total_supply: StorageValue<u64>,
+//!         pub balances: StorageMap<NeutronAddress, u64>,
+//!     }
+//! }
+//!
+//! static STORAGE: TokenStorage = TokenStorage::new();
+//!
+//! const _: () = assert!(unique_prefixes(&[&TokenStorage::LAYOUT, &OwnableStorage::LAYOUT]));
+//! ```
+//!
+//! Prefixes only depend on the namespace and field names, so fields can be reordered or added across contract upgrades,
+//! but renaming a field or the namespace moves its state. The exported `LAYOUT` lists every field with its kind of storage,
+//! the ABI names of its key and value types, and its prefix, so that tooling can compare the layouts of two versions of a contract.
+//! These don't depend on how the field type is spelled, so aliases and paths to the same type give the same layout.
+
+use crate::codec::*;
+use crate::collections::*;
+use crate::gas::*;
+use crate::interface::*;
+use crate::storage::*;
+
+pub const LAYOUT_PREFIX_SIZE: usize = 4;
+
+/// Description of a storage layout, exported by `storage_layout!`
+#[derive(core::fmt::Debug)]
+pub struct StorageLayout {
+    pub namespace: &'static str,
+    pub fields: &'static [StorageField],
+}
+
+#[derive(core::fmt::Debug)]
+pub struct StorageField {
+    pub name: &'static str,
+    /// The kind of storage, such as "StorageMap"
+    pub kind: &'static str,
+    /// The ABI names of the key and value types, keys first
+    pub types: &'static [&'static str],
+    pub prefix: [u8; LAYOUT_PREFIX_SIZE],
+}
+
+/// A type which can be a field of a `storage_layout!` struct
+pub trait LayoutField {
+    /// The kind of storage, such as "StorageMap"
+    const KIND: &'static str;
+    /// The ABI names of the key and value types, keys first
+    const TYPES: &'static [&'static str];
+}
+
+impl<T: CostackEncode> LayoutField for StorageValue<T> {
+    const KIND: &'static str = "StorageValue";
+    const TYPES: &'static [&'static str] = &[T::ABI_NAME];
+}
+
+impl<K: CostackEncode + ?Sized, V: CostackEncode> LayoutField for StorageMap<K, V> {
+    const KIND: &'static str = "StorageMap";
+    const TYPES: &'static [&'static str] = &[K::ABI_NAME, V::ABI_NAME];
+}
+
+impl<T: CostackEncode> LayoutField for StorageVec<T> {
+    const KIND: &'static str = "StorageVec";
+    const TYPES: &'static [&'static str] = &[T::ABI_NAME];
+}
+
+impl<T: CostackEncode> LayoutField for StorageDeque<T> {
+    const KIND: &'static str = "StorageDeque";
+    const TYPES: &'static [&'static str] = &[T::ABI_NAME];
+}
+
+impl<K: CostackEncode, V: CostackEncode> LayoutField for EnumerableStorageMap<K, V> {
+    const KIND: &'static str = "EnumerableStorageMap";
+    const TYPES: &'static [&'static str] = &[K::ABI_NAME, V::ABI_NAME];
+}
+
+impl LayoutField for GasCursor {
+    const KIND: &'static str = "GasCursor";
+    const TYPES: &'static [&'static str] = &[u64::ABI_NAME];
+}
+
+/// The key prefix of a field within a namespace
+pub const fn layout_prefix(namespace: &str, field: &str) -> [u8; LAYOUT_PREFIX_SIZE] {
+    let mut hash = fnv1a(namespace.as_bytes());
+    hash = fnv1a_continue(hash, b".");
+    hash = fnv1a_continue(hash, field.as_bytes());
+    let mut prefix = hash.to_le_bytes();
+    // Keys starting with the reserved prefix can't be written by contracts, any collision this causes is still detected
+    if !writable_prefix(&prefix) {
+        prefix[0] = 0;
+    }
+    prefix
+}
+
+const fn same_prefix(a: &[u8; LAYOUT_PREFIX_SIZE], b: &[u8; LAYOUT_PREFIX_SIZE]) -> bool {
+    let mut i = 0;
+    while i < LAYOUT_PREFIX_SIZE {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// True if no two fields of the given layouts share a prefix
+pub const fn unique_prefixes(layouts: &[&StorageLayout]) -> bool {
+    let mut layout = 0;
+    while layout < layouts.len() {
+        let mut field = 0;
+        while field < layouts[layout].fields.len() {
+            let prefix = &layouts[layout].fields[field].prefix;
+            // Only compare against the fields after this one
+            let mut other_layout = layout;
+            let mut other_field = field + 1;
+            while other_layout < layouts.len() {
+                while other_field < layouts[other_layout].fields.len() {
+                    if same_prefix(prefix, &layouts[other_layout].fields[other_field].prefix) {
+                        return false;
+                    }
+                    other_field += 1;
+                }
+                other_layout += 1;
+                other_field = 0;
+            }
+            field += 1;
+        }
+        layout += 1;
+    }
+    true
+}
+
+/// Declare a struct of storage fields with namespaced key prefixes. See the `layout` module.
+///
+/// Every field type must implement `LayoutField` and have a `const fn new(prefix: &'static [u8])` constructor,
+/// like the types in `storage` and `collections`.
+#[macro_export]
+macro_rules! storage_layout {
+    (
+        #[namespace = $NAMESPACE:literal]
+        $(#[$META:meta])*
+        $VIS:vis struct $NAME:ident {
+            $(
+                $(#[$FIELD_META:meta])*
+                $FIELD_VIS:vis $FIELD:ident : $FIELD_TYPE:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$META])*
+        $VIS struct $NAME {
+            $($(#[$FIELD_META])* $FIELD_VIS $FIELD: $FIELD_TYPE,)*
+        }
+
+        impl $NAME {
+            pub const LAYOUT: $crate::layout::StorageLayout = $crate::layout::StorageLayout {
+                namespace: $NAMESPACE,
+                fields: &[$($crate::layout::StorageField {
+                    name: stringify!($FIELD),
+                    kind: <$FIELD_TYPE as $crate::layout::LayoutField>::KIND,
+                    types: <$FIELD_TYPE as $crate::layout::LayoutField>::TYPES,
+                    prefix: $crate::layout::layout_prefix($NAMESPACE, stringify!($FIELD)),
+                },)*],
+            };
+
+            pub const fn new() -> $NAME {
+                $NAME {
+                    $($FIELD: <$FIELD_TYPE>::new(&const { $crate::layout::layout_prefix($NAMESPACE, stringify!($FIELD)) }),)*
+                }
+            }
+        }
+
+        const _: () = assert!(
+            $crate::layout::unique_prefixes(&[&$NAME::LAYOUT]),
+            "storage layout fields have colliding key prefixes"
+        );
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use neutron_common::NeutronAddress;
+
+    type Balances = StorageMap<NeutronAddress, u64>;
+
+    storage_layout! {
+        #[namespace = "token"]
+        struct TokenStorage {
+            total_supply: crate::storage::StorageValue<u64>,
+            balances: Balances,
+            holders: EnumerableStorageMap<NeutronAddress, u64>,
+            names: StorageMap<str, [u8; 4]>,
+        }
+    }
+
+    #[test]
+    fn fields_export_canonical_types() {
+        let fields = TokenStorage::LAYOUT.fields;
+        assert_eq!(fields[0].kind, "StorageValue");
+        assert_eq!(fields[0].types, ["u64"]);
+        // Aliases are resolved
+        assert_eq!(fields[1].kind, "StorageMap");
+        assert_eq!(fields[1].types, ["address", "u64"]);
+        assert_eq!(fields[2].kind, "EnumerableStorageMap");
+        assert_eq!(fields[2].types, ["address", "u64"]);
+        assert_eq!(fields[3].types, ["string", "bytes4"]);
+    }
+
+    #[test]
+    fn fields_use_their_exported_prefix() {
+        static STORAGE: TokenStorage = TokenStorage::new();
+        let fields = TokenStorage::LAYOUT.fields;
+        assert_eq!(STORAGE.total_supply.key(), fields[0].prefix);
+        assert_eq!(STORAGE.balances.prefix(), fields[1].prefix);
+        assert_eq!(STORAGE.holders.prefix(), fields[2].prefix);
+        assert_eq!(STORAGE.names.prefix(), layout_prefix("token", "names"));
+    }
+}
@@ -1,15 +1,50 @@
 
 #![no_std]
 
+#[cfg(test)]
+extern crate std;
+
 extern crate neutron_star_rt;
 pub mod syscalls;
 #[macro_use]
+pub mod element;
+#[macro_use]
 pub mod testing;
 pub mod logging;
 pub mod storage;
+pub mod cache;
+pub mod journal;
+pub mod call;
+#[macro_use]
+pub mod codec;
+pub mod proxy;
+pub mod context;
+pub mod gas;
+pub mod coins;
+pub mod tx;
+pub mod hashing;
+pub mod collections;
+#[macro_use]
+pub mod layout;
+#[macro_use]
+pub mod versioning;
+#[macro_use]
+pub mod packed;
+#[macro_use]
+pub mod events;
+#[macro_use]
+pub mod interface;
+#[cfg(test)]
+mod mock;
+
+pub use neutron_common::NeutronAddress;
 
 #[no_mangle]
 pub extern "C" fn __init_neutron() {
+    // Static calls run in view mode for their whole execution, so that writes fail before reaching the hypervisor
+    if let Ok(context::ExecutionMode::Static) = context::execution_mode() {
+        core::mem::forget(storage::ViewGuard::enter());
+    }
 }
 /*
 #[panic_handler]
@@ -18,9 +18,9 @@ The expense of reordering the strings etc is a cost on the CallSystem. This coul
 but since logging is informative only and can easily be a no-op (other than needing to pop off appropriate number of stack items) this incurs no real risk.
 
 Note in neutron-star, log_info is used by default for println!
-*/
 
-const LOGGING_ELEMENT: u32 = 4;
+If the logging element is not available, all logging functions are a no-op.
+*/
 
 enum LoggingFunctions{
     LogDebug = 1,
@@ -29,42 +29,61 @@ enum LoggingFunctions{
     LogError
 }
 
+system_element!{
+    const LOGGING_ELEMENT = 2;
+
+    // The message parts are pushed by the caller, and discarded by the wrapper if the element is not available
+    fn element_log_debug(count: u8, ..count) = LoggingFunctions::LogDebug as u32;
+    fn element_log_info(count: u8, ..count) = LoggingFunctions::LogInfo as u32;
+    fn element_log_warning(count: u8, ..count) = LoggingFunctions::LogWarning as u32;
+    fn element_log_error(count: u8, ..count) = LoggingFunctions::LogError as u32;
+}
+
+fn finish_log(result: Result<u32, SystemError>){
+    match result{
+        Ok(_) | Err(SystemError::ElementUnavailable) => (),
+        Err(error) => panic!("logging failed: {:?}", error)
+    }
+}
 
 pub fn log_debug(msg: &str){
+    if !element_available(LOGGING_ELEMENT){
+        return;
+    }
     push_costack(msg.as_bytes());
-    push_costack(&[1]);
-    _system_call(LOGGING_ELEMENT, LoggingFunctions::LogDebug as u32).unwrap();
+    log_debug_from_costack(1);
 }
 pub fn log_info(msg: &str){
+    if !element_available(LOGGING_ELEMENT){
+        return;
+    }
     push_costack(msg.as_bytes());
-    push_costack(&[1]);
-    _system_call(LOGGING_ELEMENT, LoggingFunctions::LogInfo as u32).unwrap();
+    log_info_from_costack(1);
 }
 pub fn log_warning(msg: &str){
+    if !element_available(LOGGING_ELEMENT){
+        return;
+    }
     push_costack(msg.as_bytes());
-    push_costack(&[1]);
-    _system_call(LOGGING_ELEMENT, LoggingFunctions::LogWarning as u32).unwrap();
+    log_warning_from_costack(1);
 }
 pub fn log_error(msg: &str){
+    if !element_available(LOGGING_ELEMENT){
+        return;
+    }
     push_costack(msg.as_bytes());
-    push_costack(&[1]);
-    _system_call(LOGGING_ELEMENT, LoggingFunctions::LogError as u32).unwrap();
+    log_error_from_costack(1);
 }
 
 pub fn log_debug_from_costack(count: u8){
-    push_costack(&[count]);
-    _system_call(LOGGING_ELEMENT, LoggingFunctions::LogDebug as u32).unwrap();
+    finish_log(element_log_debug(count));
 }
 pub fn log_info_from_costack(count: u8){
-    push_costack(&[count]);
-    _system_call(LOGGING_ELEMENT, LoggingFunctions::LogInfo as u32).unwrap();
+    finish_log(element_log_info(count));
 }
 pub fn log_warning_from_costack(count: u8){
-    push_costack(&[count]);
-    _system_call(LOGGING_ELEMENT, LoggingFunctions::LogWarning as u32).unwrap();
+    finish_log(element_log_warning(count));
 }
 pub fn log_error_from_costack(count: u8){
-    push_costack(&[count]);
-    _system_call(LOGGING_ELEMENT, LoggingFunctions::LogError as u32).unwrap();
+    finish_log(element_log_error(count));
 }
-
@@ -0,0 +1,199 @@
+//! Mock hypervisor for unit tests
+//!
+//! Supplies the runtime functions normally provided by the hypervisor, backed by an in-memory costack
+//! and the global storage element. Every test thread has its own costack, comap and storage.
+//! A single comap serves as the input, outgoing and result comap, so that values written for a call can be read back
+//! as if they had been passed to or returned by it. The hashing element returns a deterministic stand-in for sha256,
+//! which is enough to tell hashed keys apart. Elements other than global storage and hashing are unavailable.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+use std::vec::Vec;
+
+use crate::hashing::SHA256_SIZE;
+use crate::storage::GlobalStorageFunctions;
+
+const GLOBAL_STORAGE_ELEMENT: u32 = 1;
+const HASHING_ELEMENT: u32 = 10;
+const SHA256: u32 = 1;
+
+/// Comap entries by key, as the ABI value header and the value
+type Comap = BTreeMap<Vec<u8>, (u32, Vec<u8>)>;
+
+std::thread_local! {
+    static COSTACK: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+    static STORAGE: RefCell<BTreeMap<Vec<u8>, Vec<u8>>> = const { RefCell::new(BTreeMap::new()) };
+    static CALLS: RefCell<Vec<(u32, u32)>> = const { RefCell::new(Vec::new()) };
+    static COMAP: RefCell<Comap> = const { RefCell::new(BTreeMap::new()) };
+}
+
+const ERROR_RESULT: u32 = 0x8000_0000;
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Run tests one at a time, with the cache and journal reset
+///
+/// The mock is per thread, but the cache and journal are global, so every test using the mock holds this guard.
+pub fn serial() -> MutexGuard<'static, ()> {
+    let guard = SERIAL.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    crate::cache::reset();
+    crate::journal::reset();
+    guard
+}
+
+fn push(item: Vec<u8>) {
+    COSTACK.with(|costack| costack.borrow_mut().push(item));
+}
+
+fn pop() -> Vec<u8> {
+    COSTACK
+        .with(|costack| costack.borrow_mut().pop())
+        .expect("element popped from an empty costack")
+}
+
+/// The system calls made on this thread, as (element, function), excluding availability checks
+pub fn calls() -> Vec<(u32, u32)> {
+    CALLS.with(|calls| calls.borrow().clone())
+}
+
+pub fn clear_calls() {
+    CALLS.with(|calls| calls.borrow_mut().clear());
+}
+
+/// The number of items left on the costack
+pub fn costack_len() -> usize {
+    COSTACK.with(|costack| costack.borrow().len())
+}
+
+/// The value stored under a key, bypassing the storage API
+pub fn stored(key: &[u8]) -> Option<Vec<u8>> {
+    STORAGE.with(|storage| storage.borrow().get(key).cloned())
+}
+
+/// Store a value bypassing the storage API, like another contract modifying the state of this one
+pub fn set_stored(key: &[u8], value: &[u8]) {
+    STORAGE.with(|storage| storage.borrow_mut().insert(key.to_vec(), value.to_vec()));
+}
+
+fn global_storage(function: u32) -> u32 {
+    match function {
+        f if f == GlobalStorageFunctions::StoreState as u32 => {
+            let key = pop();
+            let value = pop();
+            STORAGE.with(|storage| storage.borrow_mut().insert(key, value));
+            0
+        }
+        f if f == GlobalStorageFunctions::LoadState as u32 => {
+            let key = pop();
+            match stored(&key) {
+                Some(value) => {
+                    push(value);
+                    1
+                }
+                None => 0,
+            }
+        }
+        f if f == GlobalStorageFunctions::KeyExists as u32 => {
+            let key = pop();
+            push(std::vec![stored(&key).is_some() as u8]);
+            0
+        }
+        f if f == GlobalStorageFunctions::DeleteState as u32 => {
+            let key = pop();
+            STORAGE.with(|storage| storage.borrow_mut().remove(&key));
+            0
+        }
+        _ => ERROR_RESULT,
+    }
+}
+
+/// A 32 byte FNV-1a digest standing in for sha256, which the mock has no need to compute
+pub fn digest(data: &[u8]) -> Vec<u8> {
+    let mut state: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut hash = Vec::with_capacity(SHA256_SIZE);
+    while hash.len() < SHA256_SIZE {
+        for byte in data {
+            state = (state ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+        state = (state ^ hash.len() as u64).wrapping_mul(0x0100_0000_01b3);
+        hash.extend_from_slice(&state.to_le_bytes());
+    }
+    hash
+}
+
+fn hashing(function: u32) -> u32 {
+    match function {
+        SHA256 => {
+            let data = pop();
+            push(digest(&data));
+            0
+        }
+        _ => ERROR_RESULT,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn __push_costack(buffer: *const u8, size: usize) {
+    push(unsafe { std::slice::from_raw_parts(buffer, size) }.to_vec());
+}
+
+#[no_mangle]
+pub extern "C" fn __pop_costack(buffer: *mut u8, max_size: usize) -> u32 {
+    match COSTACK.with(|costack| costack.borrow_mut().pop()) {
+        Some(item) => {
+            let size = item.len().min(max_size);
+            if size > 0 {
+                unsafe { std::ptr::copy_nonoverlapping(item.as_ptr(), buffer, size) };
+            }
+            item.len() as u32
+        }
+        None => ERROR_RESULT + 1,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn __system_call(element: u32, function: u32) -> u32 {
+    if function == 0 {
+        // Only global storage and hashing are available, at version 1
+        return (element == GLOBAL_STORAGE_ELEMENT || element == HASHING_ELEMENT) as u32;
+    }
+    CALLS.with(|calls| calls.borrow_mut().push((element, function)));
+    match element {
+        GLOBAL_STORAGE_ELEMENT => global_storage(function),
+        HASHING_ELEMENT => hashing(function),
+        _ => ERROR_RESULT,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn __push_comap(abi_data: u32) {
+    let value = pop();
+    let key = pop();
+    COMAP.with(|comap| comap.borrow_mut().insert(key, (abi_data, value)));
+}
+
+#[no_mangle]
+pub extern "C" fn __peek_comap(begin: usize, max_size: usize) -> u32 {
+    let key = pop();
+    match COMAP.with(|comap| comap.borrow().get(&key).cloned()) {
+        Some((abi_data, value)) => {
+            let begin = begin.min(value.len());
+            let end = begin.saturating_add(max_size).min(value.len());
+            push(value[begin..end].to_vec());
+            abi_data
+        }
+        // Missing keys are reported with an invalid ABI value
+        None => u32::MAX,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn __peek_result_comap(begin: usize, max_size: usize) -> u32 {
+    __peek_comap(begin, max_size)
+}
+
+#[no_mangle]
+pub extern "C" fn __revert_execution(status: u32) -> ! {
+    panic!("execution reverted with status {}", status);
+}
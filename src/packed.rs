@@ -0,0 +1,368 @@
+//! Bit-packed storage of small fields
+//!
+//! Every storage entry has a cost, so storing each flag separately is wasteful. `packed_storage!` declares a struct of small fields,
+//! such as bools, small integers and enums, which is kept packed into as few bytes as possible. Every field is declared with the
+//! names of its getter and setter, and is read and modified through them, for instance with `StorageValue::update`:
+//!
+//! ```ignore
+//! packed_storage! {
+//!     #[derive(Clone, Copy)]
+//!     pub struct Config {
+//!         pub paused, set_paused: bool,
+//!         pub fee_percent, set_fee_percent: u8 : 7,
+//!         pub mode, set_mode: Mode : 2,
+//!     }
+//! }
+//!
+//! static CONFIG: StorageValue<Config> = StorageValue::new(b"config");
+//!
+//! CONFIG.update(|config| config.set_fee_percent(fee))??;
+//! ```
+//!
+//! A field uses the number of bits given after its type, or `PackedField::BITS` of its type if none is given,
+//! which can't be more than `PackedField::BITS`. All fields together can use at most MAX_PACKED_BITS.
+//! Setters fail with `PackedError::FieldTooLarge` for values which don't fit in the bits of their field,
+//! so every struct can be encoded.
+//!
+//! Every packed struct implements `Default`, setting each field to the default value of its type, so the types of all fields
+//! must implement `Default` and the struct can't derive it. All bits 0 aren't necessarily a valid value, such as for an enum
+//! without a 0 discriminant. The default value of a field must fit in its bits, otherwise `default` panics.
+
+/// The largest number of bits all fields of a packed struct can use
+pub const MAX_PACKED_BITS: u32 = 64;
+
+#[derive(Clone, Copy, PartialEq, core::fmt::Debug)]
+pub enum PackedError {
+    /// The value given for the named field doesn't fit in its bits
+    FieldTooLarge(&'static str),
+}
+
+/// A value which can be stored in a field of a `packed_storage!` struct
+///
+/// Enums can implement this by converting to and from their discriminant.
+pub trait PackedField: Sized {
+    /// The number of bits used when a field doesn't specify it
+    const BITS: u32;
+
+    fn to_bits(&self) -> u64;
+    /// None if the bits are not a valid value
+    fn from_bits(bits: u64) -> Option<Self>;
+}
+
+impl PackedField for bool {
+    const BITS: u32 = 1;
+
+    fn to_bits(&self) -> u64 {
+        *self as u64
+    }
+
+    fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_packed_field_integer {
+    ($TYPE:tt) => {
+        impl PackedField for $TYPE {
+            const BITS: u32 = <$TYPE>::BITS;
+
+            fn to_bits(&self) -> u64 {
+                *self as u64
+            }
+
+            fn from_bits(bits: u64) -> Option<Self> {
+                if bits > <$TYPE>::MAX as u64 {
+                    return None;
+                }
+                Some(bits as $TYPE)
+            }
+        }
+    };
+}
+
+impl_packed_field_integer!(u8);
+impl_packed_field_integer!(u16);
+impl_packed_field_integer!(u32);
+
+/// Mask of the lowest `bits` bits
+#[doc(hidden)]
+pub const fn field_mask(bits: u32) -> u64 {
+    if bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_packed_bits {
+    ($TYPE:ty) => {
+        <$TYPE as $crate::packed::PackedField>::BITS
+    };
+    ($TYPE:ty, $BITS:expr) => {
+        $BITS
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_packed_check_bits {
+    ($TYPE:ty) => {};
+    ($TYPE:ty, $BITS:expr) => {
+        const _: () = assert!(
+            $BITS <= <$TYPE as $crate::packed::PackedField>::BITS,
+            "packed field uses more bits than its type"
+        );
+    };
+}
+
+// Generates the getter and setter of every field, with `$OFFSET` the number of bits used by the fields before it
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_packed_accessors {
+    ([$OFFSET:expr]) => {};
+    (
+        [$OFFSET:expr]
+        $(#[$FIELD_META:meta])*
+        $FIELD_VIS:vis $FIELD:ident, $SETTER:ident : $FIELD_TYPE:ty $(: $BITS:expr)?;
+        $($REST:tt)*
+    ) => {
+        $(#[$FIELD_META])*
+        $FIELD_VIS fn $FIELD(&self) -> $FIELD_TYPE {
+            const OFFSET: u32 = $OFFSET;
+            let mask = $crate::packed::field_mask($crate::__neutron_packed_bits!($FIELD_TYPE $(, $BITS)?));
+            match $crate::packed::PackedField::from_bits(self.bits.checked_shr(OFFSET).unwrap_or(0) & mask) {
+                Some(value) => value,
+                // Only valid values are set, by the setters and by default, and decoding checks every field
+                None => unreachable!(concat!("packed field ", stringify!($FIELD), " holds an invalid value")),
+            }
+        }
+
+        /// Set the value of the field, failing if it doesn't fit in the bits of the field
+        $FIELD_VIS fn $SETTER(&mut self, value: $FIELD_TYPE) -> Result<(), $crate::packed::PackedError> {
+            const OFFSET: u32 = $OFFSET;
+            let mask = $crate::packed::field_mask($crate::__neutron_packed_bits!($FIELD_TYPE $(, $BITS)?));
+            let bits = $crate::packed::PackedField::to_bits(&value);
+            if bits & !mask != 0 {
+                return Err($crate::packed::PackedError::FieldTooLarge(stringify!($FIELD)));
+            }
+            self.bits = (self.bits & !mask.checked_shl(OFFSET).unwrap_or(0)) | bits.checked_shl(OFFSET).unwrap_or(0);
+            Ok(())
+        }
+
+        $crate::__neutron_packed_accessors! {
+            [$OFFSET + $crate::__neutron_packed_bits!($FIELD_TYPE $(, $BITS)?)]
+            $($REST)*
+        }
+    };
+}
+
+/// Declare a struct of small fields which is stored bit-packed. See the `packed` module.
+#[macro_export]
+macro_rules! packed_storage {
+    (
+        $(#[$META:meta])*
+        $VIS:vis struct $NAME:ident {
+            $(
+                $(#[$FIELD_META:meta])*
+                $FIELD_VIS:vis $FIELD:ident, $SETTER:ident : $FIELD_TYPE:ty $(: $BITS:expr)?
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$META])*
+        $VIS struct $NAME {
+            bits: u64,
+        }
+
+        impl $NAME {
+            /// The number of bits used by all fields
+            pub const PACKED_BITS: u32 = 0 $(+ $crate::__neutron_packed_bits!($FIELD_TYPE $(, $BITS)?))*;
+            const PACKED_SIZE: usize = Self::PACKED_BITS.div_ceil(8) as usize;
+
+            $crate::__neutron_packed_accessors! {
+                [0]
+                $(
+                    $(#[$FIELD_META])*
+                    $FIELD_VIS $FIELD, $SETTER : $FIELD_TYPE $(: $BITS)?;
+                )*
+            }
+        }
+
+        impl Default for $NAME {
+            fn default() -> $NAME {
+                let mut packed = $NAME { bits: 0 };
+                $(
+                    if packed.$SETTER(Default::default()).is_err() {
+                        panic!(concat!("default value of packed field ", stringify!($FIELD), " doesn't fit in its bits"));
+                    }
+                )*
+                packed
+            }
+        }
+
+        const _: () = assert!(
+            $NAME::PACKED_BITS <= $crate::packed::MAX_PACKED_BITS,
+            "packed struct fields use more than MAX_PACKED_BITS"
+        );
+        $($crate::__neutron_packed_check_bits!($FIELD_TYPE $(, $BITS)?);)*
+
+        impl $crate::codec::CostackEncode for $NAME {
+            const ABI_VALUE: u32 = $crate::syscalls::ABI_VALUE_U8 + $crate::syscalls::ABI_ARRAY_BIT;
+            const ABI_NAME: &'static str = stringify!($NAME);
+
+            fn encode(&self, buffer: &mut [u8]) -> usize {
+                buffer[..Self::PACKED_SIZE].copy_from_slice(&self.bits.to_le_bytes()[..Self::PACKED_SIZE]);
+                Self::PACKED_SIZE
+            }
+        }
+
+        impl $crate::codec::CostackDecode for $NAME {
+            const ABI_VALUE: u32 = $crate::syscalls::ABI_VALUE_U8 + $crate::syscalls::ABI_ARRAY_BIT;
+            const MAX_SIZE: usize = Self::PACKED_SIZE;
+
+            fn decode(bytes: &[u8]) -> Result<Self, $crate::codec::RecoverableError> {
+                if bytes.len() > Self::PACKED_SIZE {
+                    return Err($crate::codec::RecoverableError::StackItemTooLarge);
+                } else if bytes.len() < Self::PACKED_SIZE {
+                    return Err($crate::codec::RecoverableError::StackItemTooSmall);
+                }
+                let mut packed = [0; 8];
+                packed[..Self::PACKED_SIZE].copy_from_slice(bytes);
+                let packed = u64::from_le_bytes(packed);
+                if packed & !$crate::packed::field_mask(Self::PACKED_BITS) != 0 {
+                    return Err($crate::codec::RecoverableError::StackItemTooLarge); // TODO: Replace with neutron-star error
+                }
+                // Every field must hold a valid value, so that the getters can't fail
+                let mut offset = 0;
+                $(
+                    let bits = $crate::__neutron_packed_bits!($FIELD_TYPE $(, $BITS)?);
+                    let field_bits = packed.checked_shr(offset).unwrap_or(0) & $crate::packed::field_mask(bits);
+                    if <$FIELD_TYPE as $crate::packed::PackedField>::from_bits(field_bits).is_none() {
+                        return Err($crate::codec::RecoverableError::StackItemTooLarge); // TODO: Replace with neutron-star error
+                    }
+                    offset += bits;
+                )*
+                let _ = offset;
+                Ok($NAME { bits: packed })
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::*;
+
+    // Has no value with all bits 0
+    #[derive(Clone, Copy, Default, PartialEq, core::fmt::Debug)]
+    enum Mode {
+        Open = 1,
+        #[default]
+        Closed = 2,
+        Paused = 3,
+    }
+
+    impl PackedField for Mode {
+        const BITS: u32 = 2;
+
+        fn to_bits(&self) -> u64 {
+            *self as u64
+        }
+
+        fn from_bits(bits: u64) -> Option<Mode> {
+            match bits {
+                1 => Some(Mode::Open),
+                2 => Some(Mode::Closed),
+                3 => Some(Mode::Paused),
+                _ => None,
+            }
+        }
+    }
+
+    packed_storage! {
+        #[derive(Clone, Copy, PartialEq, core::fmt::Debug)]
+        struct Config {
+            paused, set_paused: bool,
+            fee, set_fee: u8 : 7,
+            mode, set_mode: Mode,
+            limit, set_limit: u16,
+        }
+    }
+
+    fn encoded(config: &Config) -> u64 {
+        let mut buffer = [0; 8];
+        assert_eq!(config.encode(&mut buffer), 4);
+        u64::from_le_bytes(buffer)
+    }
+
+    #[test]
+    fn field_masks() {
+        assert_eq!(field_mask(0), 0);
+        assert_eq!(field_mask(7), 0x7f);
+        assert_eq!(field_mask(63), u64::MAX >> 1);
+        assert_eq!(field_mask(64), u64::MAX);
+    }
+
+    #[test]
+    fn fields_are_packed_in_order() {
+        assert_eq!(Config::PACKED_BITS, 1 + 7 + 2 + 16);
+        let mut config = Config::default();
+        config.set_paused(true).unwrap();
+        config.set_fee(0x55).unwrap();
+        config.set_mode(Mode::Paused).unwrap();
+        config.set_limit(0xabcd).unwrap();
+        assert_eq!(encoded(&config), 1 | 0x55 << 1 | 3 << 8 | 0xabcd << 10);
+        assert!(config.paused());
+        assert_eq!(config.fee(), 0x55);
+        assert_eq!(config.mode(), Mode::Paused);
+        assert_eq!(config.limit(), 0xabcd);
+    }
+
+    #[test]
+    fn setters_keep_other_fields() {
+        let mut config = Config::default();
+        config.set_paused(true).unwrap();
+        config.set_mode(Mode::Open).unwrap();
+        config.set_limit(u16::MAX).unwrap();
+        config.set_fee(127).unwrap();
+        config.set_fee(0).unwrap();
+        assert!(config.paused());
+        assert_eq!(config.mode(), Mode::Open);
+        assert_eq!(config.limit(), u16::MAX);
+    }
+
+    #[test]
+    fn setters_reject_values_wider_than_the_field() {
+        let mut config = Config::default();
+        config.set_fee(127).unwrap();
+        assert_eq!(config.set_fee(128), Err(PackedError::FieldTooLarge("fee")));
+        assert_eq!(config.set_fee(u8::MAX), Err(PackedError::FieldTooLarge("fee")));
+        assert_eq!(config.fee(), 127);
+        assert!(!config.paused());
+        assert_eq!(config.mode(), Mode::Closed);
+    }
+
+    #[test]
+    fn default_uses_field_defaults() {
+        let config = Config::default();
+        assert_eq!(config.mode(), Mode::Closed);
+        assert_eq!(encoded(&config), 2 << 8);
+        assert_eq!(Config::decode(&(2u32 << 8).to_le_bytes()).unwrap(), config);
+    }
+
+    #[test]
+    fn decoding_checks_every_field() {
+        // Mode holds 0
+        assert!(Config::decode(&0u32.to_le_bytes()).is_err());
+        // Bits beyond the last field
+        assert!(Config::decode(&(2u32 << 8 | 1 << 26).to_le_bytes()).is_err());
+        assert!(Config::decode(&[0; 3]).is_err());
+        assert!(Config::decode(&[0; 5]).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+//! Upgradeable proxy contracts
+//!
+//! A proxy keeps the address of an implementation contract in storage and forwards every call to it
+//! using a delegate call, so the implementation runs against the storage of the proxy.
+//! Upgrading only replaces the stored implementation address, so all other state is kept.
+//!
+//! The implementation and admin addresses are kept under reserved storage keys,
+//! which contracts can not write through `store_state`, so they can never collide with implementation state.
+
+extern crate neutron_common;
+
+use neutron_common::*;
+
+use crate::call::*;
+use crate::codec::*;
+use crate::context::*;
+use crate::storage::*;
+use crate::syscalls::*;
+
+const IMPLEMENTATION_KEY: &[u8] = b"\xFFneutron-star:proxy.implementation";
+const ADMIN_KEY: &[u8] = b"\xFFneutron-star:proxy.admin";
+
+#[derive(core::fmt::Debug)]
+pub enum ProxyError {
+    /// The proxy slots have not been written yet
+    NotInitialized,
+    /// `initialize` was called on a proxy which already has an admin
+    AlreadyInitialized,
+    /// `initialize` was called outside of the constructor of the proxy
+    NotDeploying,
+    /// The caller is not the admin of the proxy
+    NotAdmin,
+    /// The forwarded call failed
+    Call(CallError),
+    System(SystemError),
+}
+
+impl From<SystemError> for ProxyError {
+    fn from(error: SystemError) -> ProxyError {
+        ProxyError::System(error)
+    }
+}
+
+impl From<CallError> for ProxyError {
+    fn from(error: CallError) -> ProxyError {
+        ProxyError::Call(error)
+    }
+}
+
+fn load_address(key: &[u8]) -> Result<NeutronAddress, ProxyError> {
+    let mut buffer = [0; core::mem::size_of::<NeutronAddress>()];
+    match load_state_fixed(key, &mut buffer)? {
+        LoadOutcome::Missing => Err(ProxyError::NotInitialized),
+        LoadOutcome::Complete { len } => NeutronAddress::decode(&buffer[..len]).map_err(|_| SystemError::UnexpectedSize.into()),
+        LoadOutcome::Truncated { .. } => Err(SystemError::UnexpectedSize.into()),
+    }
+}
+
+fn store_address(key: &[u8], address: &NeutronAddress) -> Result<(), ProxyError> {
+    let mut buffer = [0; core::mem::size_of::<NeutronAddress>()];
+    let size = address.encode(&mut buffer);
+    store_reserved_state(key, &buffer[..size])?;
+    Ok(())
+}
+
+/// Set up the proxy slots. Only allowed from the constructor of the proxy contract,
+/// as otherwise whoever calls it first on an uninitialized proxy would become its admin.
+pub fn initialize(implementation: &NeutronAddress, admin: &NeutronAddress) -> Result<(), ProxyError> {
+    if !is_deploying()? {
+        return Err(ProxyError::NotDeploying);
+    }
+    match load_address(ADMIN_KEY) {
+        Err(ProxyError::NotInitialized) => {}
+        Ok(_) => return Err(ProxyError::AlreadyInitialized),
+        Err(e) => return Err(e),
+    }
+    store_address(ADMIN_KEY, admin)?;
+    store_address(IMPLEMENTATION_KEY, implementation)
+}
+
+/// The address of the contract which calls are currently forwarded to
+pub fn implementation() -> Result<NeutronAddress, ProxyError> {
+    load_address(IMPLEMENTATION_KEY)
+}
+
+/// The address which is allowed to upgrade the proxy
+pub fn admin() -> Result<NeutronAddress, ProxyError> {
+    load_address(ADMIN_KEY)
+}
+
+/// Forward the current call, including its whole input comap, to the implementation contract.
+/// The results of the implementation are left in the outgoing comap of the proxy.
+pub fn forward(gas_limit: u64) -> Result<(), ProxyError> {
+    let implementation = implementation()?;
+    delegate_call(&implementation, gas_limit)?;
+    Ok(())
+}
+
+/// Replace the implementation contract. Only allowed when called directly by the admin.
+pub fn upgrade_to(new_implementation: &NeutronAddress) -> Result<(), ProxyError> {
+    if admin()? != sender()? {
+        return Err(ProxyError::NotAdmin);
+    }
+    store_address(IMPLEMENTATION_KEY, new_implementation)
+}
+
+/// Hand over the right to upgrade the proxy. Only allowed when called directly by the admin.
+pub fn change_admin(new_admin: &NeutronAddress) -> Result<(), ProxyError> {
+    if admin()? != sender()? {
+        return Err(ProxyError::NotAdmin);
+    }
+    store_address(ADMIN_KEY, new_admin)
+}
@@ -1,4 +1,10 @@
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::cache;
+use crate::codec::*;
+use crate::journal;
 use crate::syscalls::*;
+use crate::versioning::*;
 /*
 ## Global Storage
 
@@ -9,33 +15,358 @@ Functions:
 * store_state(key, value) -> ()
 * load_state(key) -> (value)
 * key_exists(key) -> (bool)
+* delete_state(key) -> ()
+
+load_state returns 1 as the system call result and pushes the value if the key exists.
+If it doesn't, it returns 0 and pushes nothing. An existing key can hold an empty value.
+
+Keys beginning with RESERVED_KEY_PREFIX are reserved for neutron-star, such as for the proxy slots.
+These can only be written from within neutron-star, so that contract state can never collide with them.
+The prefix is long and specific, so that contracts storing state under raw hashes practically never hit it.
+
+While in view mode, every function which writes state fails with SystemError::WriteInView without calling the element.
+
+When the cache is enabled, all functions go through it instead of calling the element directly, see the cache module.
+Inside a transaction, writes are buffered in its journal until it commits, see the journal module.
+
+StorageValue stores a single typed value under a fixed key, using the CostackEncode/CostackDecode encoding of the type
+prefixed by its storage version, if it has one. See the versioning module.
 */
 
-const GLOBAL_STORAGE_FEATURE: u32 = 1;
+pub const RESERVED_KEY_PREFIX: &[u8] = b"\xFFneutron-star:";
+
+/// True if no key starting with `prefix` can be reserved, whatever follows the prefix
+pub const fn writable_prefix(prefix: &[u8]) -> bool{
+    let mut i = 0;
+    while i < prefix.len() && i < RESERVED_KEY_PREFIX.len(){
+        if prefix[i] != RESERVED_KEY_PREFIX[i]{
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+static VIEW_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Keeps view mode enabled until dropped, after which the previous mode is restored
+pub struct ViewGuard{
+    previous: bool
+}
+
+impl ViewGuard{
+    pub fn enter() -> ViewGuard{
+        ViewGuard::enter_if(true)
+    }
+    /// Enter view mode only if `view` is set, otherwise keep the current mode
+    pub fn enter_if(view: bool) -> ViewGuard{
+        let previous = VIEW_MODE.load(Ordering::Relaxed);
+        VIEW_MODE.store(previous || view, Ordering::Relaxed);
+        ViewGuard{
+            previous
+        }
+    }
+}
+
+impl Drop for ViewGuard{
+    fn drop(&mut self){
+        VIEW_MODE.store(self.previous, Ordering::Relaxed);
+    }
+}
+
+pub fn is_view_mode() -> bool{
+    VIEW_MODE.load(Ordering::Relaxed)
+}
 
 pub enum GlobalStorageFunctions{
-    Available = 0, //reserved??
+    Available = 0, //used by element_available/element_version
     StoreState = 1,
     LoadState,
-    KeyExists
+    KeyExists,
+    DeleteState
 }
 
-pub fn store_state(key: &[u8], value: &[u8]) -> Result<(), SystemError>{
-    push_costack(value);
-    push_costack(key);
-    _system_call(GLOBAL_STORAGE_FEATURE, GlobalStorageFunctions::StoreState as u32)?;
+/// The result of loading state into a fixed size buffer
+#[derive(Clone, Copy, PartialEq, core::fmt::Debug)]
+pub enum LoadOutcome{
+    /// Nothing is stored under the key
+    Missing,
+    /// The value is larger than the buffer, which holds only the first part of it
+    Truncated{actual_len: usize},
+    /// The whole value was loaded into the first `len` bytes of the buffer
+    Complete{len: usize}
+}
+
+system_element!{
+    const GLOBAL_STORAGE_ELEMENT = 1;
+
+    fn element_store_state(key: &[u8], value: &[u8]) = GlobalStorageFunctions::StoreState as u32;
+    fn element_load_state(key: &[u8]) = GlobalStorageFunctions::LoadState as u32;
+    fn element_key_exists(key: &[u8]) -> bool = GlobalStorageFunctions::KeyExists as u32;
+    fn element_delete_state(key: &[u8]) = GlobalStorageFunctions::DeleteState as u32;
+}
+
+// The uncached functions call the element directly, and are used by the cache to fill and write back its entries
+pub(crate) fn store_state_uncached(key: &[u8], value: &[u8]) -> Result<(), SystemError>{
+    element_store_state(key, value)?;
     Ok(())
 }
 
-pub fn load_state_fixed(key: &[u8], value: &mut [u8]) -> Result<usize, SystemError>{
-    push_costack(key);
-    _system_call(GLOBAL_STORAGE_FEATURE, GlobalStorageFunctions::StoreState as u32)?;
-    Ok(pop_costack_fixed(value)? as usize)
+pub(crate) fn delete_state_uncached(key: &[u8]) -> Result<(), SystemError>{
+    element_delete_state(key)?;
+    Ok(())
+}
+
+pub(crate) fn load_state_uncached(key: &[u8], value: &mut [u8]) -> Result<LoadOutcome, SystemError>{
+    if element_load_state(key)? == 0{
+        return Ok(LoadOutcome::Missing);
+    }
+    let actual_len = pop_costack_fixed(value)? as usize;
+    if actual_len > value.len(){
+        Ok(LoadOutcome::Truncated{actual_len})
+    }else{
+        Ok(LoadOutcome::Complete{len: actual_len})
+    }
+}
+
+/// Copy a loaded value into `buffer`, keeping only its first part if it doesn't fit
+pub(crate) fn copy_loaded(value: &[u8], buffer: &mut [u8]) -> LoadOutcome{
+    let size = value.len().min(buffer.len());
+    buffer[..size].copy_from_slice(&value[..size]);
+    if value.len() > buffer.len(){
+        LoadOutcome::Truncated{actual_len: value.len()}
+    }else{
+        LoadOutcome::Complete{len: value.len()}
+    }
+}
+
+/// Check if any value, including an empty one, is stored under a key
+pub fn key_exists(key: &[u8]) -> Result<bool, SystemError>{
+    match journal::exists(key).or_else(|| cache::exists(key)){
+        Some(exists) => Ok(exists),
+        None => element_key_exists(key)
+    }
+}
+
+/// Store or, if `value` is None, delete outside of any transaction, through the cache if it is enabled
+pub(crate) fn apply_write(key: &[u8], value: Option<&[u8]>) -> Result<(), SystemError>{
+    if cache::store(key, value)?{
+        return Ok(());
+    }
+    match value{
+        Some(value) => store_state_uncached(key, value),
+        None => delete_state_uncached(key)
+    }
+}
+
+pub use crate::journal::transaction;
+
+fn check_writable_key(key: &[u8]) -> Result<(), SystemError>{
+    if key.starts_with(RESERVED_KEY_PREFIX){
+        return Err(SystemError::ReservedKey);
+    }
+    Ok(())
+}
+
+pub fn store_state(key: &[u8], value: &[u8]) -> Result<(), SystemError>{
+    check_writable_key(key)?;
+    store_reserved_state(key, value)
+}
+
+/// Store state without checking for the reserved key prefix
+pub(crate) fn store_reserved_state(key: &[u8], value: &[u8]) -> Result<(), SystemError>{
+    if is_view_mode(){
+        return Err(SystemError::WriteInView);
+    }
+    if journal::record(key, Some(value))?{
+        return Ok(());
+    }
+    apply_write(key, Some(value))
+}
+
+/// Remove the value stored under a key, so that it no longer exists. Deleting a missing key is not an error.
+pub fn delete_state(key: &[u8]) -> Result<(), SystemError>{
+    check_writable_key(key)?;
+    delete_reserved_state(key)
+}
+
+/// Delete state without checking for the reserved key prefix
+pub(crate) fn delete_reserved_state(key: &[u8]) -> Result<(), SystemError>{
+    if is_view_mode(){
+        return Err(SystemError::WriteInView);
+    }
+    if journal::record(key, None)?{
+        return Ok(());
+    }
+    apply_write(key, None)
+}
+
+/// Load the value stored under a key into `value`, which is left untouched if the key is missing
+pub fn load_state_fixed(key: &[u8], value: &mut [u8]) -> Result<LoadOutcome, SystemError>{
+    if let Some(outcome) = journal::load(key, value){
+        return Ok(outcome);
+    }
+    match cache::load(key, value)?{
+        Some(outcome) => Ok(outcome),
+        None => load_state_uncached(key, value)
+    }
+}
+
+/// Load and decode a typed value, migrating it if it was stored with an older version of T, or None if nothing is stored.
+/// Fails with UnexpectedSize if the stored bytes are not a valid T.
+pub fn load_value<T: CostackDecode>(key: &[u8]) -> Result<Option<T>, SystemError>{
+    let mut buffer = [0; MAX_ENCODED_SIZE];
+    match load_state_fixed(key, &mut buffer)?{
+        LoadOutcome::Missing => Ok(None),
+        LoadOutcome::Complete{len} => decode_stored(&buffer[..len]).map(Some).map_err(|_| SystemError::UnexpectedSize),
+        LoadOutcome::Truncated{..} => Err(SystemError::UnexpectedSize)
+    }
+}
+
+/// Encode and store a typed value, prefixed by its storage version if it has one
+pub fn store_value<T: CostackEncode + ?Sized>(key: &[u8], value: &T) -> Result<(), SystemError>{
+    if let (None, Some(bytes)) = (T::STORAGE_VERSION, value.encoded_bytes()){
+        return store_state(key, bytes);
+    }
+    let mut buffer = [0; MAX_ENCODED_SIZE];
+    let size = encode_stored(value, &mut buffer);
+    store_state(key, &buffer[..size])
+}
+
+/// A typed value stored under a fixed key
+///
+/// Values are encoded the same way as when pushed to the costack, and so must fit within MAX_ENCODED_SIZE.
+pub struct StorageValue<T>{
+    key: &'static [u8],
+    _value: PhantomData<T>
+}
+
+impl<T> StorageValue<T>{
+    pub const fn new(key: &'static [u8]) -> StorageValue<T>{
+        StorageValue{
+            key,
+            _value: PhantomData
+        }
+    }
+
+    pub fn key(&self) -> &'static [u8]{
+        self.key
+    }
 }
+
+impl<T: CostackEncode + CostackDecode> StorageValue<T>{
+    /// The stored value, or None if nothing is stored
+    pub fn get(&self) -> Result<Option<T>, SystemError>{
+        load_value(self.key)
+    }
+
+    pub fn set(&self, value: &T) -> Result<(), SystemError>{
+        store_value(self.key, value)
+    }
+
+    pub fn exists(&self) -> Result<bool, SystemError>{
+        key_exists(self.key)
+    }
+
+    pub fn clear(&self) -> Result<(), SystemError>{
+        delete_state(self.key)
+    }
+}
+
+impl<T: CostackEncode + CostackDecode + Default> StorageValue<T>{
+    /// The stored value, or the default value of T if nothing is stored
+    pub fn get_or_default(&self) -> Result<T, SystemError>{
+        Ok(self.get()?.unwrap_or_default())
+    }
+
+    /// Load the value, or the default value of T if nothing is stored, modify it with `f` and store it again
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, SystemError>{
+        let mut value = self.get_or_default()?;
+        let result = f(&mut value);
+        self.set(&value)?;
+        Ok(result)
+    }
+}
+
 /*
 pub fn load_state(key: &[u8]) -> Result<Vec<u8>, SystemError>{
     push_costack(key);
-    _system_call(GLOBAL_STORAGE_FEATURE, GlobalStorageFunctions::StoreState as u32)?;
+    _system_call(GLOBAL_STORAGE_ELEMENT, GlobalStorageFunctions::LoadState as u32)?;
     pop_sccs()
 }
-*/
\ No newline at end of file
+*/
+#[cfg(test)]
+mod tests{
+    use crate::mock;
+    use super::*;
+
+    #[test]
+    fn load_state_fixed_calls_load_state(){
+        let _serial = mock::serial();
+        store_state(b"key", b"value").unwrap();
+        mock::clear_calls();
+        let mut buffer = [0; 8];
+        load_state_fixed(b"key", &mut buffer).unwrap();
+        assert_eq!(mock::calls(), [(GLOBAL_STORAGE_ELEMENT, GlobalStorageFunctions::LoadState as u32)]);
+    }
+
+    #[test]
+    fn load_state_fixed_outcomes(){
+        let _serial = mock::serial();
+        let mut buffer = [0; 4];
+        assert_eq!(load_state_fixed(b"key", &mut buffer).unwrap(), LoadOutcome::Missing);
+        assert_eq!(buffer, [0; 4]);
+
+        store_state(b"key", b"abc").unwrap();
+        assert_eq!(load_state_fixed(b"key", &mut buffer).unwrap(), LoadOutcome::Complete{len: 3});
+        assert_eq!(&buffer[..3], b"abc");
+
+        store_state(b"key", b"abcdef").unwrap();
+        assert_eq!(load_state_fixed(b"key", &mut buffer).unwrap(), LoadOutcome::Truncated{actual_len: 6});
+        assert_eq!(&buffer, b"abcd");
+        assert_eq!(mock::costack_len(), 0);
+    }
+
+    #[test]
+    fn empty_value_exists(){
+        let _serial = mock::serial();
+        assert!(!key_exists(b"key").unwrap());
+        store_state(b"key", b"").unwrap();
+        assert!(key_exists(b"key").unwrap());
+        let mut buffer = [0; 4];
+        assert_eq!(load_state_fixed(b"key", &mut buffer).unwrap(), LoadOutcome::Complete{len: 0});
+    }
+
+    #[test]
+    fn delete_state_removes_key(){
+        let _serial = mock::serial();
+        store_state(b"key", b"value").unwrap();
+        delete_state(b"key").unwrap();
+        assert!(!key_exists(b"key").unwrap());
+        assert_eq!(mock::stored(b"key"), None);
+        // Deleting a missing key is not an error
+        delete_state(b"key").unwrap();
+    }
+
+    #[test]
+    fn reserved_keys_are_rejected(){
+        let _serial = mock::serial();
+        assert!(matches!(store_state(b"\xFFneutron-star:key", b"value"), Err(SystemError::ReservedKey)));
+        assert!(matches!(delete_state(b"\xFFneutron-star:key"), Err(SystemError::ReservedKey)));
+        assert_eq!(mock::stored(b"\xFFneutron-star:key"), None);
+        assert!(mock::calls().is_empty());
+        // Only the whole prefix is reserved
+        store_state(b"\xFFkey", b"value").unwrap();
+        store_state(b"\xFFneutron-star", b"value").unwrap();
+        assert_eq!(mock::stored(b"\xFFkey"), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn writable_prefixes(){
+        assert!(writable_prefix(b"\xFFkey"));
+        assert!(writable_prefix(b"token"));
+        assert!(!writable_prefix(b""));
+        assert!(!writable_prefix(b"\xFFneutron"));
+        assert!(!writable_prefix(b"\xFFneutron-star:proxy"));
+    }
+}
@@ -12,6 +12,7 @@ extern crate neutron_star_rt;
 
 use core::mem::transmute;
 use core::slice;
+use core::sync::atomic::{AtomicU32, Ordering};
 
 use neutron_common::*;
 use neutron_star_rt::*;
@@ -20,6 +21,14 @@ use neutron_star_rt::*;
 pub enum SystemError {
     Generic(u32),
     UnexpectedSize,
+    /// The storage key uses the prefix reserved for neutron-star itself
+    ReservedKey,
+    /// State can not be written during a view call
+    WriteInView,
+    /// The hypervisor does not provide the element
+    ElementUnavailable,
+    /// A storage transaction has no room left to buffer a write
+    JournalFull,
 }
 
 /// Minimal wrapping around a raw hypervisor call to push_costack. (Avoid unless strictly necessary)
@@ -343,6 +352,9 @@ pub const ABI_VALUE_I64: u32 = 0b0000_0111;
 // OR (or add...) above type value with this to set byte indicating array value
 pub const ABI_ARRAY_BIT: u32 = 0b0000_1000;
 
+// Addresses are written as byte arrays, by both the typed comap functions and CostackEncode
+pub const ABI_VALUE_ADDRESS: u32 = ABI_VALUE_U8 + ABI_ARRAY_BIT;
+
 // write_comap_XXX(key, value)
 
 macro_rules! write_comap_typed_with_abi {
@@ -397,7 +409,7 @@ pub fn write_comap_i64(key: &str, value: i64) {
 
 /// Write a NeutronAddress comap value
 pub fn write_comap_address(key: &str, value: NeutronAddress) {
-    write_comap_typed_with_abi!(key, value, NeutronAddress, ABI_VALUE_I8)
+    write_comap_typed_with_abi!(key, value, NeutronAddress, ABI_VALUE_ADDRESS)
 }
 
 // write_comap_array_XXX(key, array slice)
@@ -774,9 +786,9 @@ pub fn read_result_comap_fixed_array_i64(key: &str, return_slice: &mut [i64]) ->
     }
 }
 
-pub fn get_self_address() -> NeutronAddress {
-    //TODO
-    return NeutronAddress::default();
+/// Get the address of the currently executing contract. Same as `context::self_address`
+pub fn get_self_address() -> Result<NeutronAddress, SystemError> {
+    crate::context::self_address()
 }
 
 pub fn _system_call(element: u32, function: u32) -> Result<u32, SystemError> {
@@ -789,3 +801,56 @@ pub fn _system_call(element: u32, function: u32) -> Result<u32, SystemError> {
         }
     }
 }
+
+/****************************************
+**                                     **
+**  Element availability and versions  **
+**                                     **
+****************************************/
+
+// Function 0 of every element is reserved for checking availability.
+// It returns the version of the element, where version 0 means the element is not available.
+const ELEMENT_AVAILABLE_FUNCTION: u32 = 0;
+
+// Availability of elements below CACHED_ELEMENT_COUNT is cached, using 2 bits per element:
+// the low bit is set once availability is checked and the high bit is set if the element is available
+const CACHED_ELEMENT_COUNT: u32 = 16;
+static ELEMENT_AVAILABILITY: AtomicU32 = AtomicU32::new(0);
+
+/// Get the version of an element, failing with ElementUnavailable if the hypervisor does not provide it
+pub fn element_version(element: u32) -> Result<u32, SystemError> {
+    match _system_call(element, ELEMENT_AVAILABLE_FUNCTION) {
+        Ok(0) | Err(_) => Err(SystemError::ElementUnavailable),
+        Ok(version) => Ok(version),
+    }
+}
+
+/// Check if the hypervisor provides an element
+pub fn element_available(element: u32) -> bool {
+    if element >= CACHED_ELEMENT_COUNT {
+        return element_version(element).is_ok();
+    }
+    let checked_bit = 1 << (element * 2);
+    let available_bit = 2 << (element * 2);
+    let cache = ELEMENT_AVAILABILITY.load(Ordering::Relaxed);
+    if cache & checked_bit != 0 {
+        return cache & available_bit != 0;
+    }
+    let available = element_version(element).is_ok();
+    let mut cache = cache | checked_bit;
+    if available {
+        cache |= available_bit;
+    }
+    ELEMENT_AVAILABILITY.store(cache, Ordering::Relaxed);
+    available
+}
+
+/// Fail with ElementUnavailable unless the hypervisor provides an element.
+/// The safe element wrappers check this before pushing any arguments, so nothing is left on the costack.
+pub fn require_element(element: u32) -> Result<(), SystemError> {
+    if element_available(element) {
+        Ok(())
+    } else {
+        Err(SystemError::ElementUnavailable)
+    }
+}
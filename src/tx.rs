@@ -0,0 +1,230 @@
+//! Introspection of the UTXO transaction which invoked the current execution
+//!
+//! This allows covenant-like checks, for instance requiring that the transaction calling a contract
+//! also pays a certain amount to some address.
+
+/*
+## Transaction
+
+ID: 8
+
+Functions:
+
+* transaction_id() -> (hash)
+* lock_time() -> (u32)
+* input_count() -> (u32)
+* output_count() -> (u32)
+* input(index) -> (previous_txid, previous_vout, value, spender)
+* output(index) -> (value, output_type, address, script)
+
+Results are listed in the order they are popped from the costack.
+The address of an output is only meaningful for standard output types, for other outputs it is zeroed and only the script can be used.
+*/
+
+extern crate neutron_common;
+
+use neutron_common::*;
+
+use crate::codec::*;
+use crate::syscalls::*;
+
+enum TransactionFunctions {
+    TransactionId = 1,
+    LockTime,
+    InputCount,
+    OutputCount,
+    Input,
+    Output,
+}
+
+pub const TRANSACTION_ID_SIZE: usize = 32;
+/// The largest output script which is kept in full by `TxOutput`
+pub const MAX_SCRIPT_SIZE: usize = 128;
+
+#[derive(Clone, Copy, PartialEq, core::fmt::Debug)]
+pub enum OutputType {
+    PayToPubkeyHash,
+    PayToScriptHash,
+    Contract,
+    /// Data carrying output which can't be spent
+    NullData,
+    NonStandard,
+}
+
+impl CostackDecode for OutputType {
+    const ABI_VALUE: u32 = ABI_VALUE_U8;
+    const MAX_SIZE: usize = 1;
+
+    fn decode(bytes: &[u8]) -> Result<Self, RecoverableError> {
+        Ok(match u8::decode(bytes)? {
+            1 => OutputType::PayToPubkeyHash,
+            2 => OutputType::PayToScriptHash,
+            3 => OutputType::Contract,
+            4 => OutputType::NullData,
+            _ => OutputType::NonStandard,
+        })
+    }
+}
+
+/// An output script, kept in full up to MAX_SCRIPT_SIZE bytes
+struct OutputScript {
+    bytes: [u8; MAX_SCRIPT_SIZE],
+    size: usize,
+}
+
+impl CostackDecode for OutputScript {
+    const ABI_VALUE: u32 = ABI_VALUE_U8 + ABI_ARRAY_BIT;
+    const MAX_SIZE: usize = MAX_SCRIPT_SIZE;
+
+    fn decode(bytes: &[u8]) -> Result<Self, RecoverableError> {
+        if bytes.len() > MAX_SCRIPT_SIZE {
+            return Err(RecoverableError::StackItemTooLarge);
+        }
+        let mut script = OutputScript {
+            bytes: [0; MAX_SCRIPT_SIZE],
+            size: bytes.len(),
+        };
+        script.bytes[..bytes.len()].copy_from_slice(bytes);
+        Ok(script)
+    }
+
+    // Longer scripts are truncated rather than rejected, keeping their full size
+    fn pop_costack() -> Result<Self, RecoverableError> {
+        let mut bytes = [0; MAX_SCRIPT_SIZE];
+        match pop_costack_fixed(&mut bytes) {
+            Ok(size) => Ok(OutputScript {
+                bytes,
+                size: size as usize,
+            }),
+            Err(_) => Err(RecoverableError::ItemDoesntExist),
+        }
+    }
+}
+
+pub struct TxInput {
+    pub previous_txid: [u8; TRANSACTION_ID_SIZE],
+    pub previous_vout: u32,
+    pub value: u64,
+    /// The address which owned the spent output
+    pub spender: NeutronAddress,
+}
+
+pub struct TxOutput {
+    pub value: u64,
+    pub output_type: OutputType,
+    pub address: NeutronAddress,
+    script: [u8; MAX_SCRIPT_SIZE],
+    script_size: usize,
+}
+
+impl TxOutput {
+    /// The output script, truncated to MAX_SCRIPT_SIZE bytes
+    pub fn script(&self) -> &[u8] {
+        &self.script[..self.script_size.min(MAX_SCRIPT_SIZE)]
+    }
+
+    /// True if the script is longer than MAX_SCRIPT_SIZE and so `script` only returns part of it
+    pub fn script_truncated(&self) -> bool {
+        self.script_size > MAX_SCRIPT_SIZE
+    }
+}
+
+system_element! {
+    const TRANSACTION_ELEMENT = 8;
+
+    /// The id of the current transaction
+    pub fn transaction_id() -> [u8; TRANSACTION_ID_SIZE] = TransactionFunctions::TransactionId as u32;
+    pub fn lock_time() -> u32 = TransactionFunctions::LockTime as u32;
+    pub fn input_count() -> u32 = TransactionFunctions::InputCount as u32;
+    pub fn output_count() -> u32 = TransactionFunctions::OutputCount as u32;
+    fn element_input(index: u32) -> ([u8; TRANSACTION_ID_SIZE], u32, u64, NeutronAddress) = TransactionFunctions::Input as u32;
+    fn element_output(index: u32) -> (u64, OutputType, NeutronAddress, OutputScript) = TransactionFunctions::Output as u32;
+}
+
+pub fn input(index: u32) -> Result<TxInput, SystemError> {
+    let (previous_txid, previous_vout, value, spender) = element_input(index)?;
+    Ok(TxInput {
+        previous_txid,
+        previous_vout,
+        value,
+        spender,
+    })
+}
+
+pub fn output(index: u32) -> Result<TxOutput, SystemError> {
+    let (value, output_type, address, script) = element_output(index)?;
+    Ok(TxOutput {
+        value,
+        output_type,
+        address,
+        script: script.bytes,
+        script_size: script.size,
+    })
+}
+
+/// Iterate over the inputs of the current transaction
+pub fn inputs() -> Result<TxInputs, SystemError> {
+    Ok(TxInputs {
+        index: 0,
+        count: input_count()?,
+    })
+}
+
+/// Iterate over the outputs of the current transaction
+pub fn outputs() -> Result<TxOutputs, SystemError> {
+    Ok(TxOutputs {
+        index: 0,
+        count: output_count()?,
+    })
+}
+
+pub struct TxInputs {
+    index: u32,
+    count: u32,
+}
+
+impl Iterator for TxInputs {
+    type Item = Result<TxInput, SystemError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.count {
+            return None;
+        }
+        self.index += 1;
+        Some(input(self.index - 1))
+    }
+}
+
+pub struct TxOutputs {
+    index: u32,
+    count: u32,
+}
+
+impl Iterator for TxOutputs {
+    type Item = Result<TxOutput, SystemError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.count {
+            return None;
+        }
+        self.index += 1;
+        Some(output(self.index - 1))
+    }
+}
+
+/// The total value of all outputs of the current transaction paying to `address`
+pub fn total_paid_to(address: &NeutronAddress) -> Result<u64, SystemError> {
+    let mut total: u64 = 0;
+    for output in outputs()? {
+        let output = output?;
+        if output.output_type != OutputType::NonStandard && output.output_type != OutputType::NullData && output.address == *address {
+            total = total.saturating_add(output.value);
+        }
+    }
+    Ok(total)
+}
+
+/// True if the current transaction pays at least `minimum_value` to `address`
+pub fn pays_to(address: &NeutronAddress, minimum_value: u64) -> Result<bool, SystemError> {
+    Ok(total_paid_to(address)? >= minimum_value)
+}
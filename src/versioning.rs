@@ -0,0 +1,186 @@
+//! Versioned storage values which are migrated when loaded
+//!
+//! Changing a stored struct would otherwise make all state written with the old layout unreadable.
+//! Every struct declared with `neutron_struct!` is stored prefixed by the version of its type, which is 1 unless
+//! given with `#[storage_version(n)]`. When loading a value written with an older version, it is decoded using the type
+//! of that version and migrated step by step up to the current version. Migration happens lazily whenever an old value
+//! is loaded, and the value is stored in the current version the next time it is written.
+//!
+//! Every version after the first names the type of the previous version and the function migrating from it:
+//!
+//! ```ignore
+//! neutron_struct! { pub struct OrderV1 { pub amount: u32 } }
+//!
+//! neutron_struct! {
+//!     #[storage_version(2, migrate_from_v1(OrderV1))]
+//!     pub struct Order { pub amount: u64, pub filled: bool }
+//! }
+//!
+//! fn migrate_from_v1(old: OrderV1) -> Order {
+//!     Order { amount: old.amount as u64, filled: false }
+//! }
+//!
+//! static ORDERS: StorageMap<u64, Order> = StorageMap::new(b"orders");
+//! ```
+//!
+//! The version is only added by the storage functions, such as `store_value` and the types in `storage` and `collections`.
+//! The encoding of a struct in call arguments, events and on the costack stays unchanged.
+//! Since every struct is stored with a version from the start, a version can be added to any struct later on.
+//! Other types are stored without a version, unless they set `STORAGE_VERSION` in their `CostackEncode` and `CostackDecode`
+//! implementations, in which case their encoding must leave room for the version byte.
+
+use crate::codec::*;
+
+/// Encode a value as it is stored, prefixed by its version if it has one, and return the number of bytes used
+pub fn encode_stored<T: CostackEncode + ?Sized>(value: &T, buffer: &mut [u8]) -> usize {
+    match T::STORAGE_VERSION {
+        Some(version) => {
+            buffer[0] = version;
+            1 + value.encode(&mut buffer[1..])
+        }
+        None => value.encode(buffer),
+    }
+}
+
+/// Decode a stored value, migrating it if it was written with an older version of its type
+pub fn decode_stored<T: CostackDecode>(bytes: &[u8]) -> Result<T, RecoverableError> {
+    let current = match T::STORAGE_VERSION {
+        Some(current) => current,
+        None => return T::decode(bytes),
+    };
+    match bytes.split_first() {
+        Some((version, value)) if *version <= current => T::decode_version(*version, value),
+        // Written by a newer version of the contract
+        Some(_) => Err(RecoverableError::StackItemTooLarge), // TODO: Replace with neutron-star error
+        None => Err(RecoverableError::StackItemTooSmall),
+    }
+}
+
+#[doc(hidden)]
+pub const fn is_older_version(previous: Option<u8>, version: u8) -> bool {
+    match previous {
+        Some(previous) => previous < version,
+        None => false,
+    }
+}
+
+// Decodes a version older than the current one, using the previous version if the struct declares one
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_decode_previous_version {
+    ($VERSION:expr, $BYTES:expr) => {
+        Err($crate::codec::RecoverableError::ItemDoesntExist) // TODO: Replace with neutron-star error
+    };
+    ($VERSION:expr, $BYTES:expr, $MIGRATE:ident($PREVIOUS:ty)) => {
+        <$PREVIOUS as $crate::codec::CostackDecode>::decode_version($VERSION, $BYTES).map($MIGRATE)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __neutron_check_previous_version {
+    ($VERSION:literal) => {};
+    ($VERSION:literal, $MIGRATE:ident($PREVIOUS:ty)) => {
+        const _: () = assert!(
+            $crate::versioning::is_older_version(<$PREVIOUS as $crate::codec::CostackDecode>::STORAGE_VERSION, $VERSION),
+            "storage version must be greater than the version it migrates from"
+        );
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::*;
+    use crate::mock;
+    use crate::storage::*;
+    use crate::syscalls::*;
+
+    neutron_struct! {
+        struct OrderV1 {
+            amount: u32,
+        }
+    }
+
+    neutron_struct! {
+        #[storage_version(2, migrate_from_v1(OrderV1))]
+        #[derive(PartialEq, core::fmt::Debug)]
+        struct OrderV2 {
+            amount: u64,
+        }
+    }
+
+    neutron_struct! {
+        #[storage_version(3, migrate_from_v2(OrderV2))]
+        #[derive(PartialEq, core::fmt::Debug)]
+        struct Order {
+            amount: u64,
+            filled: bool,
+        }
+    }
+
+    fn migrate_from_v1(old: OrderV1) -> OrderV2 {
+        OrderV2 { amount: old.amount as u64 }
+    }
+
+    fn migrate_from_v2(old: OrderV2) -> Order {
+        Order {
+            amount: old.amount,
+            filled: false,
+        }
+    }
+
+    static ORDER_V1: StorageValue<OrderV1> = StorageValue::new(b"order");
+    static ORDER_V2: StorageValue<OrderV2> = StorageValue::new(b"order");
+    static ORDER: StorageValue<Order> = StorageValue::new(b"order");
+
+    #[test]
+    fn stored_structs_are_versioned() {
+        let _serial = mock::serial();
+        ORDER_V1.set(&OrderV1 { amount: 9 }).unwrap();
+        assert_eq!(mock::stored(b"order").unwrap()[0], 1);
+        ORDER.set(&Order { amount: 9, filled: true }).unwrap();
+        assert_eq!(mock::stored(b"order").unwrap()[0], 3);
+        // The costack encoding doesn't include the version
+        let mut buffer = [0; MAX_ENCODED_SIZE];
+        assert_eq!(OrderV1 { amount: 9 }.encode(&mut buffer), 5);
+        assert_eq!(buffer[..5], [4, 9, 0, 0, 0]);
+    }
+
+    #[test]
+    fn old_versions_are_migrated_on_load() {
+        let _serial = mock::serial();
+        ORDER_V1.set(&OrderV1 { amount: 9 }).unwrap();
+        assert_eq!(ORDER_V2.get().unwrap(), Some(OrderV2 { amount: 9 }));
+        assert_eq!(ORDER.get().unwrap(), Some(Order { amount: 9, filled: false }));
+
+        ORDER_V2.set(&OrderV2 { amount: 10 }).unwrap();
+        assert_eq!(ORDER.get().unwrap(), Some(Order { amount: 10, filled: false }));
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let _serial = mock::serial();
+        ORDER.set(&Order { amount: 9, filled: true }).unwrap();
+        assert!(matches!(ORDER_V2.get(), Err(SystemError::UnexpectedSize)));
+        assert!(matches!(ORDER_V1.get(), Err(SystemError::UnexpectedSize)));
+    }
+
+    #[test]
+    fn collections_migrate_values() {
+        let _serial = mock::serial();
+        let old: StorageMap<u64, OrderV1> = StorageMap::new(b"orders");
+        let new: StorageMap<u64, Order> = StorageMap::new(b"orders");
+        old.insert(&1, &OrderV1 { amount: 9 }).unwrap();
+        assert_eq!(new.get(&1).unwrap(), Some(Order { amount: 9, filled: false }));
+    }
+
+    #[test]
+    fn other_types_are_not_versioned() {
+        let _serial = mock::serial();
+        let value: StorageValue<u64> = StorageValue::new(b"value");
+        value.set(&7).unwrap();
+        assert_eq!(mock::stored(b"value"), Some(7u64.to_le_bytes().to_vec()));
+        assert_eq!(decode_stored::<u64>(&7u64.to_le_bytes()).unwrap(), 7);
+    }
+}